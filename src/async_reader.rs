@@ -0,0 +1,103 @@
+use crate::hprof_model::{StringId, U4, U8};
+use crate::push_parser::{ParseEvent, PushParser};
+use crate::reader::{ReaderOptions, HEADER_SIZE};
+use futures_core::Stream;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::Error;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+/// How many bytes are read from the input at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads a heap dump from an [`AsyncRead`] and yields its records and sub-records as a
+/// [`Stream`] of [`ParseEvent`]s.
+///
+/// The input is read in chunks and passed to a [`PushParser`], so sub-records are yielded as
+/// soon as their bytes have arrived, and heap dump segments are never buffered completely.
+///
+/// Unlike [`crate::reader::HprofReader`], which yields each heap dump segment as one
+/// [`crate::hprof_model::RecordTag`] with all its sub-records, this reader yields a segment as
+/// a [`ParseEvent::Record`] with empty `sub_records`, followed by a [`ParseEvent::SubRecord`]
+/// for each of its sub-records. All other records are yielded as the same `RecordTag`s. Code
+/// that handles the records of both readers can handle segments through their sub-records.
+pub struct AsyncHprofReader<R: AsyncRead + Unpin> {
+    pub identifier_size: U4,
+    pub timestamp: U8,
+    input: R,
+    parser: PushParser,
+    /// The events parsed from the last chunk that were not yielded yet
+    events: VecDeque<ParseEvent>,
+    chunk: Box<[u8]>,
+    /// Set once the input ended or an error was returned
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncHprofReader<R> {
+    pub async fn new(input: R) -> Result<AsyncHprofReader<R>, Error> {
+        Self::with_options(input, ReaderOptions::default()).await
+    }
+
+    pub async fn with_options(
+        mut input: R,
+        options: ReaderOptions,
+    ) -> Result<AsyncHprofReader<R>, Error> {
+        let mut header = [0; HEADER_SIZE];
+        input.read_exact(&mut header).await?;
+        let mut parser = PushParser::with_options(options);
+        parser.feed(&header)?;
+        Ok(AsyncHprofReader {
+            identifier_size: parser.identifier_size().expect("the header is complete"),
+            timestamp: parser.timestamp().expect("the header is complete"),
+            input,
+            parser,
+            events: VecDeque::new(),
+            chunk: vec![0; CHUNK_SIZE].into_boxed_slice(),
+            done: false,
+        })
+    }
+
+    /// Returns the next record or sub-record, or `None` at the end of the input.
+    pub async fn next_event(&mut self) -> Option<Result<ParseEvent, Error>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Returns the string with the given id, if it was parsed already.
+    pub fn name(&self, id: StringId) -> Result<Option<Cow<'_, str>>, Error> {
+        self.parser.name(id)
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncHprofReader<R> {
+    type Item = Result<ParseEvent, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(event) = this.events.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+            let mut read_buf = ReadBuf::new(&mut this.chunk);
+            let parsed = match ready!(Pin::new(&mut this.input).poll_read(cx, &mut read_buf)) {
+                Ok(()) if read_buf.filled().is_empty() => {
+                    this.done = true;
+                    return Poll::Ready(this.parser.finish().err().map(Err));
+                }
+                Ok(()) => this.parser.feed(read_buf.filled()),
+                Err(e) => Err(e),
+            };
+            match parsed {
+                Ok(parsed) => this.events.extend(parsed.events),
+                Err(e) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::hprof_model::{ClassId, ClassInfo, RecordBase, StringId, U1, U4, U8};
+use crate::hprof_model::{ClassSerial, FrameId, ObjectId, ThreadSerial, TraceSerial, I4};
+use crate::object_index::ObjectIndexBuilder;
+use crate::stack_trace::{FrameInfo, StackTraceTable};
+use crate::storage::{Storable, Table};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::sync::Arc;
+
+/// Identifies checkpoints written by [`crate::reader::HprofReader::write_checkpoint`].
+const MAGIC: &[u8] = b"HPROF-RS CHECKPOINT 4\0";
+
+/// The state of a reader that is not kept in its tables.
+///
+/// A checkpoint starts with [`MAGIC`], followed by these fields, the strings, the classes,
+/// the name ids of the classes, the stack frames, the stack traces, the class ids by
+/// class serial number and the offsets of the indexed objects. Each table entry is `1u8`,
+/// the id, the length of the encoded value and the encoded value. A single `0u8` ends the
+/// entries of a table. All numbers are big-endian.
+pub(crate) struct CheckpointState {
+    pub identifier_size: U4,
+    pub timestamp: U8,
+    /// The position in the (decompressed) input
+    pub offset: U8,
+    /// The segment that was being read and the position where it ends
+    pub segment: Option<(RecordBase, U8)>,
+}
+
+pub(crate) fn write_checkpoint(
+    out: &mut impl Write,
+    state: &CheckpointState,
+    names: &dyn Table<str>,
+    classes: &dyn Table<ClassInfo>,
+    class_names: &HashMap<ClassId, StringId>,
+    stack_traces: &StackTraceTable,
+    objects: Option<&ObjectIndexBuilder>,
+) -> Result<(), Error> {
+    out.write_all(MAGIC)?;
+    out.write_all(&state.identifier_size.to_be_bytes())?;
+    out.write_all(&state.timestamp.to_be_bytes())?;
+    out.write_all(&state.offset.to_be_bytes())?;
+    match state.segment {
+        None => out.write_all(&[0])?,
+        Some((base, end)) => {
+            out.write_all(&[1])?;
+            out.write_all(&base.micros_since.to_be_bytes())?;
+            out.write_all(&base.size_remaining.to_be_bytes())?;
+            out.write_all(&end.to_be_bytes())?;
+        }
+    }
+    write_table(out, names)?;
+    write_table(out, classes)?;
+    for (class_object_id, name_id) in class_names {
+        write_entry(out, class_object_id.0, &name_id.0.to_be_bytes())?;
+    }
+    out.write_all(&[0])?;
+    let mut bytes = vec![];
+    for (frame_id, frame) in &stack_traces.frames {
+        bytes.clear();
+        bytes.extend_from_slice(&frame.method_name_id.0.to_be_bytes());
+        bytes.extend_from_slice(&frame.method_signature_id.0.to_be_bytes());
+        bytes.extend_from_slice(&frame.source_file_name_id.0.to_be_bytes());
+        bytes.extend_from_slice(&frame.class_serial_number.0.to_be_bytes());
+        bytes.extend_from_slice(&frame.line_number.to_be_bytes());
+        write_entry(out, frame_id.0, &bytes)?;
+    }
+    out.write_all(&[0])?;
+    for (serial_number, (thread_serial_number, frame_ids)) in &stack_traces.traces {
+        bytes.clear();
+        bytes.extend_from_slice(&thread_serial_number.0.to_be_bytes());
+        for frame_id in frame_ids {
+            bytes.extend_from_slice(&frame_id.0.to_be_bytes());
+        }
+        write_entry(out, serial_number.0 as U8, &bytes)?;
+    }
+    out.write_all(&[0])?;
+    for (serial_number, class_object_id) in &stack_traces.class_serials {
+        write_entry(out, serial_number.0 as U8, &class_object_id.0.to_be_bytes())?;
+    }
+    out.write_all(&[0])?;
+    for (object_id, offset) in objects.map_or(&[][..], ObjectIndexBuilder::entries) {
+        write_entry(out, object_id.0, &offset.to_be_bytes())?;
+    }
+    out.write_all(&[0])?;
+    out.flush()
+}
+
+fn write_table<V: Storable + ?Sized>(
+    out: &mut impl Write,
+    table: &dyn Table<V>,
+) -> Result<(), Error> {
+    let mut bytes = vec![];
+    for id in table.ids() {
+        let Some(value) = table.get(id)? else {
+            continue;
+        };
+        bytes.clear();
+        value.encode(&mut bytes);
+        write_entry(out, id, &bytes)?;
+    }
+    out.write_all(&[0])
+}
+
+fn write_entry(out: &mut impl Write, id: U8, bytes: &[U1]) -> Result<(), Error> {
+    out.write_all(&[1])?;
+    out.write_all(&id.to_be_bytes())?;
+    out.write_all(&(bytes.len() as U4).to_be_bytes())?;
+    out.write_all(bytes)
+}
+
+/// Reads a checkpoint and inserts its entries into the given tables. The indexed objects
+/// are only added if `objects` is `Some`.
+pub(crate) fn read_checkpoint(
+    input: &mut impl Read,
+    names: &mut dyn Table<str>,
+    classes: &mut dyn Table<ClassInfo>,
+    class_names: &mut HashMap<ClassId, StringId>,
+    stack_traces: &mut StackTraceTable,
+    mut objects: Option<&mut ObjectIndexBuilder>,
+) -> Result<CheckpointState, Error> {
+    let mut magic = [0; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a checkpoint"));
+    }
+    let identifier_size = U4::from_be_bytes(take(input)?);
+    let timestamp = U8::from_be_bytes(take(input)?);
+    let offset = U8::from_be_bytes(take(input)?);
+    let segment = match take::<1>(input)? {
+        [0] => None,
+        _ => {
+            let base = RecordBase {
+                micros_since: U4::from_be_bytes(take(input)?),
+                size_remaining: U4::from_be_bytes(take(input)?),
+            };
+            Some((base, U8::from_be_bytes(take(input)?)))
+        }
+    };
+    read_table(input, |id, bytes| {
+        let name: Arc<str> = Arc::from(str::decode(bytes)?);
+        names.insert(id, &name)
+    })?;
+    read_table(input, |id, bytes| {
+        let class = Arc::new(ClassInfo::decode(bytes)?.into_owned());
+        classes.insert(id, &class)
+    })?;
+    read_table(input, |id, mut bytes| {
+        class_names.insert(ClassId(id), StringId(U8::from_be_bytes(take(&mut bytes)?)));
+        Ok(())
+    })?;
+    read_table(input, |id, mut bytes| {
+        let frame = FrameInfo {
+            method_name_id: StringId(U8::from_be_bytes(take(&mut bytes)?)),
+            method_signature_id: StringId(U8::from_be_bytes(take(&mut bytes)?)),
+            source_file_name_id: StringId(U8::from_be_bytes(take(&mut bytes)?)),
+            class_serial_number: ClassSerial(U4::from_be_bytes(take(&mut bytes)?)),
+            line_number: I4::from_be_bytes(take(&mut bytes)?),
+        };
+        stack_traces.frames.insert(FrameId(id), frame);
+        Ok(())
+    })?;
+    read_table(input, |id, mut bytes| {
+        let thread_serial_number = ThreadSerial(U4::from_be_bytes(take(&mut bytes)?));
+        let frame_ids = bytes
+            .chunks_exact(8)
+            .map(|c| FrameId(U8::from_be_bytes(c.try_into().unwrap())))
+            .collect();
+        let serial_number = TraceSerial(id as U4);
+        stack_traces
+            .traces
+            .insert(serial_number, (thread_serial_number, frame_ids));
+        Ok(())
+    })?;
+    read_table(input, |id, mut bytes| {
+        let class_object_id = ClassId(U8::from_be_bytes(take(&mut bytes)?));
+        stack_traces
+            .class_serials
+            .insert(ClassSerial(id as U4), class_object_id);
+        Ok(())
+    })?;
+    read_table(input, |id, mut bytes| {
+        let offset = U8::from_be_bytes(take(&mut bytes)?);
+        if let Some(objects) = &mut objects {
+            objects.add(ObjectId(id), offset);
+        }
+        Ok(())
+    })?;
+    Ok(CheckpointState {
+        identifier_size,
+        timestamp,
+        offset,
+        segment,
+    })
+}
+
+fn read_table(
+    input: &mut impl Read,
+    mut insert: impl FnMut(U8, &[U1]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut bytes = vec![];
+    while take::<1>(input)? != [0] {
+        let id = U8::from_be_bytes(take(input)?);
+        let len = U4::from_be_bytes(take(input)?);
+        bytes.resize(len as usize, 0);
+        input.read_exact(&mut bytes)?;
+        insert(id, &bytes)?;
+    }
+    Ok(())
+}
+
+fn take<const N: usize>(input: &mut impl Read) -> Result<[u8; N], Error> {
+    let mut buf = [0; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
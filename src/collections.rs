@@ -0,0 +1,575 @@
+use crate::decode::{field, invalid_data};
+use crate::heap::Heap;
+use crate::hprof_model::{Identifier, ObjectId, Value};
+use crate::instance::Instance;
+use std::collections::HashSet;
+use std::io::{Error, Read, Seek};
+
+/// The JDK collection classes that [`collection`] can walk.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CollectionKind {
+    ArrayList,
+    CopyOnWriteArrayList,
+    LinkedList,
+    ArrayDeque,
+    HashMap,
+    LinkedHashMap,
+    HashSet,
+    LinkedHashSet,
+    ConcurrentHashMap,
+    TreeMap,
+}
+
+impl CollectionKind {
+    /// Returns the kind of the class with the given name in JVM form.
+    pub fn from_class_name(class_name: &str) -> Option<CollectionKind> {
+        let kind = match class_name {
+            "java/util/ArrayList" => CollectionKind::ArrayList,
+            "java/util/concurrent/CopyOnWriteArrayList" => CollectionKind::CopyOnWriteArrayList,
+            "java/util/LinkedList" => CollectionKind::LinkedList,
+            "java/util/ArrayDeque" => CollectionKind::ArrayDeque,
+            "java/util/HashMap" => CollectionKind::HashMap,
+            "java/util/LinkedHashMap" => CollectionKind::LinkedHashMap,
+            "java/util/HashSet" => CollectionKind::HashSet,
+            "java/util/LinkedHashSet" => CollectionKind::LinkedHashSet,
+            "java/util/concurrent/ConcurrentHashMap" => CollectionKind::ConcurrentHashMap,
+            "java/util/TreeMap" => CollectionKind::TreeMap,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    pub fn is_map(&self) -> bool {
+        matches!(
+            self,
+            CollectionKind::HashMap
+                | CollectionKind::LinkedHashMap
+                | CollectionKind::ConcurrentHashMap
+                | CollectionKind::TreeMap
+        )
+    }
+}
+
+/// The size and the use of the backing storage of a collection.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CollectionStats {
+    /// The number of elements or entries that were found.
+    pub size: usize,
+    /// The length of the backing array, `None` for linked structures.
+    pub capacity: Option<usize>,
+    /// The number of non-empty bins of hash tables.
+    pub used_bins: usize,
+    /// The number of entries in the longest bin of hash tables.
+    pub max_bin_length: usize,
+    /// The number of bins of hash tables that were converted to trees.
+    pub tree_bins: usize,
+}
+
+impl CollectionStats {
+    /// The size relative to the capacity, `None` for linked structures and empty arrays.
+    pub fn load(&self) -> Option<f64> {
+        match self.capacity {
+            Some(capacity) if capacity > 0 => Some(self.size as f64 / capacity as f64),
+            _ => None,
+        }
+    }
+}
+
+/// The contents of a collection, read by walking its internal structure.
+#[derive(Clone, Debug)]
+pub struct Collection {
+    pub kind: CollectionKind,
+    /// The elements in iteration order. For maps, the keys.
+    pub elements: Vec<ObjectId>,
+    /// For maps, the values in the order of their keys. Empty for other collections.
+    pub values: Vec<ObjectId>,
+    pub stats: CollectionStats,
+}
+
+impl Collection {
+    /// Returns the key and the value of each entry of a map.
+    pub fn entries(&self) -> impl Iterator<Item = (ObjectId, ObjectId)> + '_ {
+        self.elements
+            .iter()
+            .copied()
+            .zip(self.values.iter().copied())
+    }
+}
+
+/// An element of a collection, or an entry of a map.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Entry {
+    /// The element, or the key of a map entry.
+    pub element: ObjectId,
+    /// The value of a map entry, `None` for other collections.
+    pub value: Option<ObjectId>,
+}
+
+/// Walks an instance of one of the classes of [`CollectionKind`] and collects its contents,
+/// see [`walk`]. Returns `None` for instances of other classes, including subclasses.
+pub fn collection<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    instance: &Instance,
+) -> Result<Option<Collection>, Error> {
+    let Some(mut walk) = walk(heap, instance)? else {
+        return Ok(None);
+    };
+    let mut collection = Collection {
+        kind: walk.kind(),
+        elements: vec![],
+        values: vec![],
+        stats: CollectionStats::default(),
+    };
+    for entry in walk.by_ref() {
+        let entry = entry?;
+        collection.elements.push(entry.element);
+        collection.values.extend(entry.value);
+    }
+    collection.stats = *walk.stats();
+    Ok(Some(collection))
+}
+
+/// Like [`collection`], but looks the instance up first. Returns `None` for null and for
+/// objects that are not instances.
+pub fn collection_of<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    object_id: ObjectId,
+) -> Result<Option<Collection>, Error> {
+    match heap.instance(object_id)? {
+        Some(instance) => collection(heap, &instance),
+        None => Ok(None),
+    }
+}
+
+/// Returns an iterator over the elements or map entries of an instance of one of the classes
+/// of [`CollectionKind`], which reads the nodes of the collection as it advances. Returns
+/// `None` for instances of other classes, including subclasses.
+///
+/// Entries are found by walking the backing arrays and nodes, not by trusting size fields,
+/// so collections that were modified while the dump was written are read as far as possible.
+pub fn walk<'a, T: Read + Seek, I: Identifier>(
+    heap: &'a mut Heap<T, I>,
+    instance: &Instance,
+) -> Result<Option<CollectionWalk<'a, T, I>>, Error> {
+    let class_name = heap.reader().class_name(instance.class_object_id())?;
+    let Some(kind) = class_name
+        .as_deref()
+        .and_then(CollectionKind::from_class_name)
+    else {
+        return Ok(None);
+    };
+    let state = match kind {
+        CollectionKind::ArrayList => {
+            let size = field(instance, "size", Value::as_int)?;
+            let array = object_array(heap, field(instance, "elementData", Value::as_object)?)?;
+            let size = usize::try_from(size).unwrap_or_default().min(array.len());
+            State::Array {
+                array,
+                position: 0,
+                remaining: size,
+            }
+        }
+        CollectionKind::CopyOnWriteArrayList => {
+            let array = object_array(heap, field(instance, "array", Value::as_object)?)?;
+            State::Array {
+                remaining: array.len(),
+                array,
+                position: 0,
+            }
+        }
+        CollectionKind::LinkedList => State::Linked {
+            node_id: field(instance, "first", Value::as_object)?,
+        },
+        CollectionKind::ArrayDeque => {
+            let array = object_array(heap, field(instance, "elements", Value::as_object)?)?;
+            let head = field(instance, "head", Value::as_int)?;
+            let tail = field(instance, "tail", Value::as_int)?;
+            let (Ok(head), Ok(tail)) = (usize::try_from(head), usize::try_from(tail)) else {
+                return Err(invalid_data("negative head or tail of ArrayDeque"));
+            };
+            if !array.is_empty() && (head >= array.len() || tail >= array.len()) {
+                return Err(invalid_data("head or tail of ArrayDeque out of bounds"));
+            }
+            let remaining = match array.len() {
+                0 => 0,
+                len => (tail + len - head) % len,
+            };
+            State::Array {
+                array,
+                position: head,
+                remaining,
+            }
+        }
+        CollectionKind::HashMap | CollectionKind::LinkedHashMap => {
+            State::HashTable(HashTable::new(heap, instance)?)
+        }
+        CollectionKind::HashSet | CollectionKind::LinkedHashSet => {
+            let map_id = field(instance, "map", Value::as_object)?;
+            let map = Walker::new(heap).node(map_id)?;
+            State::HashTable(HashTable::new(heap, &map)?)
+        }
+        CollectionKind::ConcurrentHashMap => {
+            let table_id = field(instance, "table", Value::as_object)?;
+            State::ConcurrentTable(ConcurrentTable {
+                tables: vec![object_array(heap, table_id)?],
+                bin: 0,
+                pending: vec![],
+                used: false,
+                node_id: ObjectId::default(),
+                length: 0,
+            })
+        }
+        CollectionKind::TreeMap => State::Tree {
+            stack: vec![],
+            node_id: field(instance, "root", Value::as_object)?,
+        },
+    };
+    let capacity = match &state {
+        State::Array { array, .. } => Some(array.len()),
+        State::HashTable(table) => Some(table.table.len()),
+        State::ConcurrentTable(table) => Some(table.tables[0].len()),
+        State::Linked { .. } | State::Tree { .. } => None,
+    };
+    Ok(Some(CollectionWalk {
+        kind,
+        walker: Walker::new(heap),
+        state,
+        stats: CollectionStats {
+            capacity,
+            ..CollectionStats::default()
+        },
+        done: false,
+    }))
+}
+
+/// Iterates over the elements or map entries of a collection, see [`walk`]. Sets yield
+/// their elements without values.
+pub struct CollectionWalk<'a, T: Read + Seek, I: Identifier> {
+    kind: CollectionKind,
+    walker: Walker<'a, T, I>,
+    state: State,
+    stats: CollectionStats,
+    /// Set after an error, which ends the walk
+    done: bool,
+}
+
+/// Where a [`CollectionWalk`] continues.
+enum State {
+    /// The elements of a backing array from `position` on, wrapping around at its end
+    Array {
+        array: Vec<ObjectId>,
+        position: usize,
+        remaining: usize,
+    },
+    /// The nodes of a `LinkedList`
+    Linked {
+        node_id: ObjectId,
+    },
+    /// The nodes of a `TreeMap` in order: the node `node_id` with its left descendants,
+    /// then the nodes on the stack
+    Tree {
+        stack: Vec<Instance>,
+        node_id: ObjectId,
+    },
+    HashTable(HashTable),
+    ConcurrentTable(ConcurrentTable),
+}
+
+impl<T: Read + Seek, I: Identifier> CollectionWalk<'_, T, I> {
+    pub fn kind(&self) -> CollectionKind {
+        self.kind
+    }
+
+    /// The statistics of the entries walked so far, which are complete once the walk is
+    /// exhausted.
+    pub fn stats(&self) -> &CollectionStats {
+        &self.stats
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry>, Error> {
+        let walker = &mut self.walker;
+        let entry = match &mut self.state {
+            State::Array {
+                array,
+                position,
+                remaining,
+            } => {
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+                let element = array[*position];
+                *position = (*position + 1) % array.len();
+                *remaining -= 1;
+                Entry {
+                    element,
+                    value: None,
+                }
+            }
+            State::Linked { node_id } => {
+                if node_id.is_null() {
+                    return Ok(None);
+                }
+                let node = walker.node(*node_id)?;
+                *node_id = field(&node, "next", Value::as_object)?;
+                Entry {
+                    element: field(&node, "item", Value::as_object)?,
+                    value: None,
+                }
+            }
+            State::Tree { stack, node_id } => {
+                while !node_id.is_null() {
+                    let node = walker.node(*node_id)?;
+                    *node_id = field(&node, "left", Value::as_object)?;
+                    stack.push(node);
+                }
+                let Some(node) = stack.pop() else {
+                    return Ok(None);
+                };
+                *node_id = field(&node, "right", Value::as_object)?;
+                map_entry(&node, "value")?
+            }
+            State::HashTable(table) => match table.next_entry(walker, &mut self.stats)? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            },
+            State::ConcurrentTable(table) => match table.next_entry(walker, &mut self.stats)? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            },
+        };
+        self.stats.size += 1;
+        if matches!(
+            self.kind,
+            CollectionKind::HashSet | CollectionKind::LinkedHashSet
+        ) {
+            return Ok(Some(Entry {
+                value: None,
+                ..entry
+            }));
+        }
+        Ok(Some(entry))
+    }
+}
+
+impl<T: Read + Seek, I: Identifier> Iterator for CollectionWalk<'_, T, I> {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = self.next_entry().transpose();
+        self.done = !matches!(entry, Some(Ok(_)));
+        entry
+    }
+}
+
+/// Returns the key and the value of a map node.
+fn map_entry(node: &Instance, value_field: &str) -> Result<Entry, Error> {
+    Ok(Entry {
+        element: field(node, "key", Value::as_object)?,
+        value: Some(field(node, value_field, Value::as_object)?),
+    })
+}
+
+/// Walks the table of a `HashMap` or `LinkedHashMap`. The entries of a `LinkedHashMap` are
+/// returned in the order of its linked list, followed by the entries that are not reachable
+/// through it in table order.
+struct HashTable {
+    table: Vec<ObjectId>,
+    /// The next bin
+    bin: usize,
+    /// The next node of the current bin
+    node_id: ObjectId,
+    /// The number of nodes of the current bin so far
+    length: usize,
+    /// The next node of the linked list of a `LinkedHashMap`, and the nodes on it so far
+    linked: Option<(ObjectId, HashSet<ObjectId>)>,
+}
+
+impl HashTable {
+    fn new<T: Read + Seek, I: Identifier>(
+        heap: &mut Heap<T, I>,
+        map: &Instance,
+    ) -> Result<HashTable, Error> {
+        let linked = match map.get("head") {
+            Some(_) => Some((field(map, "head", Value::as_object)?, HashSet::new())),
+            None => None,
+        };
+        Ok(HashTable {
+            table: object_array(heap, field(map, "table", Value::as_object)?)?,
+            bin: 0,
+            node_id: ObjectId::default(),
+            length: 0,
+            linked,
+        })
+    }
+
+    fn next_entry<T: Read + Seek, I: Identifier>(
+        &mut self,
+        walker: &mut Walker<T, I>,
+        stats: &mut CollectionStats,
+    ) -> Result<Option<Entry>, Error> {
+        if let Some((node_id, linked_nodes)) = &mut self.linked {
+            if !node_id.is_null() {
+                let node = walker.node(*node_id)?;
+                linked_nodes.insert(*node_id);
+                *node_id = field(&node, "after", Value::as_object)?;
+                if node_id.is_null() {
+                    // the nodes are read again for the table
+                    walker.reset();
+                }
+                return map_entry(&node, "value").map(Some);
+            }
+        }
+        loop {
+            if self.node_id.is_null() {
+                let Some(&bin) = self.table.get(self.bin) else {
+                    return Ok(None);
+                };
+                self.bin += 1;
+                if !bin.is_null() {
+                    stats.used_bins += 1;
+                    self.node_id = bin;
+                    self.length = 0;
+                }
+                continue;
+            }
+            let node_id = self.node_id;
+            let node = walker.node(node_id)?;
+            // tree bins keep their nodes linked through next, too
+            if self.length == 0 && walker.class_name(&node)? == "java/util/HashMap$TreeNode" {
+                stats.tree_bins += 1;
+            }
+            self.length += 1;
+            stats.max_bin_length = stats.max_bin_length.max(self.length);
+            self.node_id = field(&node, "next", Value::as_object)?;
+            let on_linked_list = self
+                .linked
+                .as_ref()
+                .is_some_and(|(_, linked_nodes)| linked_nodes.contains(&node_id));
+            if !on_linked_list {
+                return map_entry(&node, "value").map(Some);
+            }
+        }
+    }
+}
+
+/// Walks the table of a `ConcurrentHashMap`. Bins that were already moved by a resize in
+/// progress are read from the new table, which is read once, because all forwarding nodes
+/// of a table point to it.
+struct ConcurrentTable {
+    /// The table, followed by the new table of each resize in progress
+    tables: Vec<Vec<ObjectId>>,
+    /// The next bin of the first table
+    bin: usize,
+    /// The bins of new tables that the current bin was moved to, as pairs of the index of
+    /// the table and the bin
+    pending: Vec<(usize, usize)>,
+    /// Whether the current bin of the first table has an entry
+    used: bool,
+    /// The next node of the current bin
+    node_id: ObjectId,
+    /// The number of nodes of the current bin so far
+    length: usize,
+}
+
+impl ConcurrentTable {
+    fn next_entry<T: Read + Seek, I: Identifier>(
+        &mut self,
+        walker: &mut Walker<T, I>,
+        stats: &mut CollectionStats,
+    ) -> Result<Option<Entry>, Error> {
+        loop {
+            if !self.node_id.is_null() {
+                let node = walker.node(self.node_id)?;
+                self.node_id = field(&node, "next", Value::as_object)?;
+                self.length += 1;
+                stats.max_bin_length = stats.max_bin_length.max(self.length);
+                if !self.used {
+                    self.used = true;
+                    stats.used_bins += 1;
+                }
+                return map_entry(&node, "val").map(Some);
+            }
+            let (depth, i) = match self.pending.pop() {
+                Some(pending) => pending,
+                None if self.bin < self.tables[0].len() => {
+                    self.bin += 1;
+                    self.used = false;
+                    (0, self.bin - 1)
+                }
+                None => return Ok(None),
+            };
+            let bin = self.tables[depth].get(i).copied().unwrap_or_default();
+            if bin.is_null() {
+                continue;
+            }
+            self.length = 0;
+            let head = walker.node(bin)?;
+            self.node_id = match walker.class_name(&head)?.as_str() {
+                "java/util/concurrent/ConcurrentHashMap$TreeBin" => {
+                    stats.tree_bins += 1;
+                    field(&head, "first", Value::as_object)?
+                }
+                "java/util/concurrent/ConcurrentHashMap$ForwardingNode" => {
+                    if self.tables.len() == depth + 1 {
+                        let next_table_id = field(&head, "nextTable", Value::as_object)?;
+                        self.tables.push(object_array(walker.heap, next_table_id)?);
+                    }
+                    // a bin is split into the bins i and i + n of the doubled table
+                    let n = self.tables[depth].len();
+                    self.pending.extend([(depth + 1, i + n), (depth + 1, i)]);
+                    continue;
+                }
+                "java/util/concurrent/ConcurrentHashMap$ReservationNode" => continue,
+                _ => bin,
+            };
+        }
+    }
+}
+
+fn object_array<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    object_id: ObjectId,
+) -> Result<Vec<ObjectId>, Error> {
+    if object_id.is_null() {
+        return Ok(vec![]);
+    }
+    heap.object_array(object_id)?
+        .ok_or_else(|| invalid_data("expected an object array"))
+}
+
+/// Reads the nodes of a linked structure and fails if there are more nodes than objects
+/// in the dump, which means that the nodes form a cycle.
+struct Walker<'a, T: Read + Seek, I: Identifier> {
+    heap: &'a mut Heap<T, I>,
+    remaining: usize,
+}
+
+impl<'a, T: Read + Seek, I: Identifier> Walker<'a, T, I> {
+    fn new(heap: &'a mut Heap<T, I>) -> Self {
+        let remaining = heap.index().len();
+        Walker { heap, remaining }
+    }
+
+    /// Allows to read as many nodes again, for another walk over the same nodes.
+    fn reset(&mut self) {
+        self.remaining = self.heap.index().len();
+    }
+
+    fn node(&mut self, node_id: ObjectId) -> Result<Instance, Error> {
+        if self.remaining == 0 {
+            return Err(invalid_data("the nodes of the collection form a cycle"));
+        }
+        self.remaining -= 1;
+        self.heap
+            .instance(node_id)?
+            .ok_or_else(|| invalid_data("expected an instance"))
+    }
+
+    fn class_name(&mut self, instance: &Instance) -> Result<String, Error> {
+        let class_name = self.heap.reader().class_name(instance.class_object_id())?;
+        Ok(class_name.map(|n| n.into_owned()).unwrap_or_default())
+    }
+}
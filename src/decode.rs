@@ -0,0 +1,423 @@
+use crate::heap::Heap;
+use crate::hprof_model::{Identifier, ObjectId, PrimitiveArray, Value};
+use crate::instance::Instance;
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind, Read, Seek};
+
+/// The value of an instance of a well-known JDK class, see [`value`].
+#[derive(Clone, PartialEq, Debug)]
+pub enum JavaValue {
+    /// A `java.lang.Boolean` or `java.util.concurrent.atomic.AtomicBoolean`.
+    Boolean(bool),
+    /// A `java.lang.Character`, which may be half of a surrogate pair.
+    Char(u16),
+    Byte(i8),
+    Short(i16),
+    /// A `java.lang.Integer` or `java.util.concurrent.atomic.AtomicInteger`.
+    Int(i32),
+    /// A `java.lang.Long` or `java.util.concurrent.atomic.AtomicLong`.
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    BigInteger(BigInteger),
+    BigDecimal(BigDecimal),
+    /// A `java.util.Date`, in milliseconds since 1970-01-01T00:00:00Z.
+    Date(i64),
+    /// A `java.util.UUID`, with the most significant bits first.
+    Uuid(u128),
+    /// An enum constant.
+    Enum {
+        ordinal: i32,
+        name: String,
+    },
+}
+
+/// Strings are quoted, dates are formatted in ISO 8601 and enum constants by their name.
+impl Display for JavaValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JavaValue::Boolean(v) => write!(f, "{v}"),
+            JavaValue::Char(v) => match char::from_u32(*v as u32) {
+                Some(c) => write!(f, "{c:?}"),
+                None => write!(f, "'\\u{v:04x}'"),
+            },
+            JavaValue::Byte(v) => write!(f, "{v}"),
+            JavaValue::Short(v) => write!(f, "{v}"),
+            JavaValue::Int(v) => write!(f, "{v}"),
+            JavaValue::Long(v) => write!(f, "{v}"),
+            JavaValue::Float(v) => write!(f, "{v}"),
+            JavaValue::Double(v) => write!(f, "{v}"),
+            JavaValue::String(v) => write!(f, "{v:?}"),
+            JavaValue::BigInteger(v) => write!(f, "{v}"),
+            JavaValue::BigDecimal(v) => write!(f, "{v}"),
+            JavaValue::Date(millis) => write_date(f, *millis),
+            JavaValue::Uuid(v) => write!(
+                f,
+                "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+                v >> 96,
+                (v >> 80) & 0xFFFF,
+                (v >> 64) & 0xFFFF,
+                (v >> 48) & 0xFFFF,
+                v & 0xFFFF_FFFF_FFFF
+            ),
+            JavaValue::Enum { name, .. } => write!(f, "{name}"),
+        }
+    }
+}
+
+/// A `java.math.BigInteger`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BigInteger {
+    /// -1, 0 or 1.
+    pub signum: i32,
+    /// The absolute value as 32-bit words, most significant first.
+    pub magnitude: Vec<u32>,
+}
+
+impl BigInteger {
+    /// Returns the value if it fits.
+    pub fn to_i128(&self) -> Option<i128> {
+        let mut magnitude: u128 = 0;
+        for &word in self.magnitude.iter().skip_while(|&&w| w == 0) {
+            magnitude = magnitude.checked_mul(1 << 32)? | word as u128;
+        }
+        match self.signum {
+            -1 => 0i128.checked_sub_unsigned(magnitude),
+            _ => i128::try_from(magnitude).ok(),
+        }
+    }
+}
+
+impl From<i64> for BigInteger {
+    fn from(v: i64) -> Self {
+        let magnitude = v.unsigned_abs();
+        let words = [(magnitude >> 32) as u32, magnitude as u32];
+        BigInteger {
+            signum: v.signum() as i32,
+            magnitude: words.into_iter().skip_while(|&w| w == 0).collect(),
+        }
+    }
+}
+
+/// Prints the value in decimal.
+impl Display for BigInteger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        const BASE: u64 = 1_000_000_000;
+        let mut words: Vec<u32> = self.magnitude.clone();
+        // base 10^9 digits, least significant first
+        let mut digits = vec![];
+        loop {
+            let zeros = words.iter().take_while(|&&w| w == 0).count();
+            words.drain(..zeros);
+            if words.is_empty() {
+                break;
+            }
+            let mut remainder = 0;
+            for word in &mut words {
+                let current = (remainder << 32) | *word as u64;
+                *word = (current / BASE) as u32;
+                remainder = current % BASE;
+            }
+            digits.push(remainder);
+        }
+        let Some((first, rest)) = digits.split_last() else {
+            return write!(f, "0");
+        };
+        if self.signum < 0 {
+            write!(f, "-")?;
+        }
+        write!(f, "{first}")?;
+        for digit in rest.iter().rev() {
+            write!(f, "{digit:09}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A `java.math.BigDecimal`, which is `unscaled * 10^-scale`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BigDecimal {
+    pub unscaled: BigInteger,
+    pub scale: i32,
+}
+
+/// Prints the value without an exponent, like `BigDecimal.toPlainString()`.
+impl Display for BigDecimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let unscaled = self.unscaled.to_string();
+        let (sign, digits) = match unscaled.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", unscaled.as_str()),
+        };
+        let scale = self.scale.unsigned_abs() as usize;
+        if self.scale <= 0 {
+            let zeros = if digits == "0" { 0 } else { scale };
+            return write!(f, "{sign}{digits}{}", "0".repeat(zeros));
+        }
+        let digits = format!("{digits:0>width$}", width = scale + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{sign}{integer}.{fraction}")
+    }
+}
+
+/// Decodes an instance of a boxed primitive, an atomic number, `String`, `BigInteger`,
+/// `BigDecimal`, `java.util.Date` or `UUID`, or an enum constant.
+///
+/// Returns `None` for instances of other classes. Dates are read from `fastTime`, which is
+/// up to date unless the date was modified through deprecated setters.
+pub fn value<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    instance: &Instance,
+) -> Result<Option<JavaValue>, Error> {
+    let Some(class_name) = heap.reader().class_name(instance.class_object_id())? else {
+        return Ok(None);
+    };
+    let value = match &*class_name {
+        "java/lang/Boolean" => JavaValue::Boolean(field(instance, "value", Value::as_boolean)?),
+        "java/lang/Character" => JavaValue::Char(field(instance, "value", Value::as_char)?),
+        "java/lang/Byte" => JavaValue::Byte(field(instance, "value", Value::as_byte)?),
+        "java/lang/Short" => JavaValue::Short(field(instance, "value", Value::as_short)?),
+        "java/lang/Integer" | "java/util/concurrent/atomic/AtomicInteger" => {
+            JavaValue::Int(field(instance, "value", Value::as_int)?)
+        }
+        "java/lang/Long" | "java/util/concurrent/atomic/AtomicLong" => {
+            JavaValue::Long(field(instance, "value", Value::as_long)?)
+        }
+        "java/lang/Float" => JavaValue::Float(field(instance, "value", Value::as_float)?),
+        "java/lang/Double" => JavaValue::Double(field(instance, "value", Value::as_double)?),
+        "java/util/concurrent/atomic/AtomicBoolean" => {
+            JavaValue::Boolean(field(instance, "value", Value::as_int)? != 0)
+        }
+        "java/lang/String" => JavaValue::String(string(heap, instance)?),
+        "java/math/BigInteger" => JavaValue::BigInteger(big_integer(heap, instance)?),
+        "java/math/BigDecimal" => JavaValue::BigDecimal(big_decimal(heap, instance)?),
+        "java/util/Date" => JavaValue::Date(field(instance, "fastTime", Value::as_long)?),
+        "java/util/UUID" => {
+            let most = field(instance, "mostSigBits", Value::as_long)? as u64;
+            let least = field(instance, "leastSigBits", Value::as_long)? as u64;
+            JavaValue::Uuid((most as u128) << 64 | least as u128)
+        }
+        _ => return enum_constant(heap, instance),
+    };
+    Ok(Some(value))
+}
+
+/// Like [`value`], but looks the instance up first. Returns `None` for null and for objects
+/// that are not instances.
+pub fn value_of<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    object_id: ObjectId,
+) -> Result<Option<JavaValue>, Error> {
+    match heap.instance(object_id)? {
+        Some(instance) => value(heap, &instance),
+        None => Ok(None),
+    }
+}
+
+/// Decodes an instance of `java.lang.String` by reading its backing array.
+///
+/// Supports the layouts of JDK 9 and later, where `value` is a `byte[]` with Latin-1 or
+/// UTF-16 contents depending on `coder`, of JDK 6 to 8, where `value` is a `char[]` that
+/// may be shared and is sliced with `offset` and `count`, and of Android, where `value` is a
+/// `char[]` or a Latin-1 `byte[]` that holds exactly the string. UTF-16 contents of `byte[]`
+/// are taken to be little-endian, as on all common platforms. Unpaired surrogates are
+/// replaced with U+FFFD.
+pub fn string<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    string: &Instance,
+) -> Result<String, Error> {
+    expect_class(heap, string, "java/lang/String")?;
+    let value = string
+        .get_ref("value")
+        .ok_or_else(|| invalid_data("the string has no value field"))?;
+    let array = heap
+        .primitive_array(value)?
+        .ok_or_else(|| invalid_data("the value of the string is not a primitive array"))?;
+    match array {
+        PrimitiveArray::Byte(bytes) => match string.get("coder").and_then(Value::as_byte) {
+            // Android has no coder and only compresses Latin-1 strings
+            None | Some(0) => Ok(bytes.iter().map(|&b| b as u8 as char).collect()),
+            Some(1) => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0] as u8, c[1] as u8]))
+                    .collect();
+                Ok(String::from_utf16_lossy(&units))
+            }
+            Some(coder) => Err(invalid_data(format!("unknown string coder {coder}"))),
+        },
+        PrimitiveArray::Char(chars) => {
+            let units = match (string.get_int("offset"), string.get_int("count")) {
+                (Some(offset), Some(count)) => usize::try_from(offset)
+                    .ok()
+                    .zip(usize::try_from(count).ok())
+                    .and_then(|(offset, count)| chars.get(offset..offset.checked_add(count)?))
+                    .ok_or_else(|| invalid_data("the string exceeds its value"))?,
+                _ => &chars[..],
+            };
+            Ok(String::from_utf16_lossy(units))
+        }
+        _ => Err(invalid_data(
+            "the value of the string is neither a byte[] nor a char[]",
+        )),
+    }
+}
+
+fn big_integer<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    instance: &Instance,
+) -> Result<BigInteger, Error> {
+    let signum = field(instance, "signum", Value::as_int)?;
+    let mag = field(instance, "mag", Value::as_object)?;
+    let Some(PrimitiveArray::Int(magnitude)) = heap.primitive_array(mag)? else {
+        return Err(invalid_data(
+            "the magnitude of the BigInteger is not an int[]",
+        ));
+    };
+    Ok(BigInteger {
+        signum,
+        magnitude: magnitude.into_iter().map(|w| w as u32).collect(),
+    })
+}
+
+fn big_decimal<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    instance: &Instance,
+) -> Result<BigDecimal, Error> {
+    let scale = field(instance, "scale", Value::as_int)?;
+    // values that fit into a long are kept in intCompact, otherwise it is Long.MIN_VALUE
+    let compact = field(instance, "intCompact", Value::as_long)?;
+    if compact != i64::MIN {
+        return Ok(BigDecimal {
+            unscaled: BigInteger::from(compact),
+            scale,
+        });
+    }
+    let int_val = field(instance, "intVal", Value::as_object)?;
+    let int_val = heap
+        .instance(int_val)?
+        .ok_or_else(|| invalid_data("the BigDecimal has no intVal"))?;
+    Ok(BigDecimal {
+        unscaled: big_integer(heap, &int_val)?,
+        scale,
+    })
+}
+
+/// Decodes the instance if `java.lang.Enum` declares some of its fields.
+fn enum_constant<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    instance: &Instance,
+) -> Result<Option<JavaValue>, Error> {
+    let mut enum_class = None;
+    for (class_id, name, _) in instance.fields() {
+        if name == Some("ordinal")
+            && heap.reader().class_name(class_id)?.as_deref() == Some("java/lang/Enum")
+        {
+            enum_class = Some(class_id);
+        }
+    }
+    let Some(enum_class) = enum_class else {
+        return Ok(None);
+    };
+    let ordinal = instance.get_declared(enum_class, "ordinal");
+    let name_id = instance.get_declared(enum_class, "name");
+    let (Some(ordinal), Some(name_id)) = (
+        ordinal.and_then(Value::as_int),
+        name_id.and_then(Value::as_object),
+    ) else {
+        return Err(invalid_data("the enum constant has no ordinal or name"));
+    };
+    let Some(name) = heap.instance(name_id)? else {
+        return Err(invalid_data("the enum constant has no name"));
+    };
+    let name = string(heap, &name)?;
+    Ok(Some(JavaValue::Enum { ordinal, name }))
+}
+
+/// Returns the value of a field, failing if it is missing or has another type.
+pub(crate) fn field<V>(
+    instance: &Instance,
+    name: &str,
+    as_type: impl FnOnce(&Value) -> Option<V>,
+) -> Result<V, Error> {
+    instance
+        .get(name)
+        .and_then(as_type)
+        .ok_or_else(|| invalid_data(format!("no {name} field of the expected type")))
+}
+
+/// Writes milliseconds since the epoch like `2024-02-29T12:00:00.000Z`.
+fn write_date(f: &mut Formatter<'_>, millis: i64) -> std::fmt::Result {
+    let days = millis.div_euclid(86_400_000);
+    let millis_of_day = millis.rem_euclid(86_400_000);
+    // the civil date of a day since the epoch, after Howard Hinnant
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    write!(
+        f,
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        millis_of_day / 3_600_000,
+        millis_of_day / 60_000 % 60,
+        millis_of_day / 1000 % 60,
+        millis_of_day % 1000
+    )
+}
+
+/// Fails unless the instance is of the class with the given name in JVM form.
+fn expect_class<T: Read + Seek, I: Identifier>(
+    heap: &mut Heap<T, I>,
+    instance: &Instance,
+    class_name: &str,
+) -> Result<(), Error> {
+    let name = heap.reader().class_name(instance.class_object_id())?;
+    if name.as_deref() != Some(class_name) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("not an instance of {class_name}"),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn invalid_data(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BigDecimal, BigInteger, JavaValue};
+
+    #[test]
+    fn formats_values() {
+        let big = BigInteger {
+            signum: -1,
+            magnitude: vec![1, 0, 5],
+        };
+        assert_eq!(big.to_string(), "-18446744073709551621");
+        assert_eq!(big.to_i128(), Some(-18446744073709551621));
+        let decimal = |unscaled, scale| {
+            let unscaled = BigInteger::from(unscaled);
+            BigDecimal { unscaled, scale }.to_string()
+        };
+        assert_eq!(decimal(-1234, 2), "-12.34");
+        assert_eq!(decimal(5, 3), "0.005");
+        assert_eq!(decimal(12, -2), "1200");
+        assert_eq!(
+            JavaValue::Date(951_825_600_123).to_string(),
+            "2000-02-29T12:00:00.123Z"
+        );
+        assert_eq!(
+            JavaValue::Uuid(0x123e4567_e89b_12d3_a456_426614174000).to_string(),
+            "123e4567-e89b-12d3-a456-426614174000"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use flate2::bufread::GzDecoder;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+
+pub(crate) const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Decompresses a gzip file with one or more members and allows seeking in the
+/// uncompressed data.
+///
+/// HotSpot writes compressed heap dumps (`jcmd GC.heap_dump -gz=N`) as a sequence of
+/// independently compressed members. The start of each member is remembered while
+/// reading, so seeking back only needs to decompress from the start of the member
+/// that contains the target position. Seeking forward decompresses and discards the
+/// data in between, unless the target lies in a member that is already known.
+pub struct GzipReader<R: Read + Seek> {
+    /// `None` only while switching to another member
+    decoder: Option<GzDecoder<BufReader<R>>>,
+    /// The position in the uncompressed data
+    position: u64,
+    /// Compressed and uncompressed start offsets of all members seen so far, in order
+    members: Vec<(u64, u64)>,
+}
+
+impl<R: Read + Seek> GzipReader<R> {
+    pub fn new(mut input: R) -> Result<GzipReader<R>, Error> {
+        let start = input.stream_position()?;
+        Ok(GzipReader {
+            decoder: Some(GzDecoder::new(BufReader::new(input))),
+            position: 0,
+            members: vec![(start, 0)],
+        })
+    }
+
+    fn decoder(&mut self) -> &mut GzDecoder<BufReader<R>> {
+        self.decoder.as_mut().unwrap()
+    }
+
+    /// Starts decompressing the member at `compressed` which starts at `uncompressed`.
+    fn open_member(&mut self, compressed: u64, uncompressed: u64) -> Result<(), Error> {
+        let mut input = self.decoder.take().unwrap().into_inner();
+        input.seek(SeekFrom::Start(compressed))?;
+        self.decoder = Some(GzDecoder::new(input));
+        self.position = uncompressed;
+        Ok(())
+    }
+
+    /// Moves to the next member after the current one ended.
+    /// Returns `false` if there are no more members.
+    fn next_member(&mut self) -> Result<bool, Error> {
+        let input = self.decoder().get_mut();
+        if input.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
+        let compressed = input.stream_position()?;
+        if self.members.last().is_some_and(|&(c, _)| c < compressed) {
+            self.members.push((compressed, self.position));
+        }
+        let input = self.decoder.take().unwrap().into_inner();
+        self.decoder = Some(GzDecoder::new(input));
+        Ok(true)
+    }
+
+    fn discard(&mut self, mut n: u64) -> Result<(), Error> {
+        let mut scratch = [0u8; 8192];
+        while n > 0 {
+            let len = n.min(scratch.len() as u64) as usize;
+            let read = self.read(&mut scratch[..len])?;
+            if read == 0 {
+                return Err(Error::from(ErrorKind::UnexpectedEof));
+            }
+            n -= read as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.decoder().read(buf)?;
+            if n > 0 {
+                self.position += n as u64;
+                return Ok(n);
+            }
+            if !self.next_member()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Seek for GzipReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.position.checked_add_signed(n),
+            SeekFrom::End(_) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "cannot seek from the end of gzip-compressed data",
+                ))
+            }
+        }
+        .ok_or(Error::from(ErrorKind::InvalidInput))?;
+        // the last known member that starts before the target
+        let member = self.members.partition_point(|&(_, u)| u <= target) - 1;
+        let (compressed, uncompressed) = self.members[member];
+        if target < self.position || uncompressed > self.position {
+            self.open_member(compressed, uncompressed)?;
+        }
+        self.discard(target - self.position)?;
+        Ok(self.position)
+    }
+
+    fn stream_position(&mut self) -> Result<u64, Error> {
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,89 @@
+use crate::hprof_model::{HeapDumpTag, Identifier, ObjectId, PrimitiveArray};
+use crate::instance::Instance;
+use crate::object_index::ObjectIndex;
+use crate::reader::HprofReader;
+use std::io::{Error, ErrorKind, Read, Seek};
+
+/// Looks up objects by their id, using an [`ObjectIndex`] of the heap dump.
+///
+/// The objects are read again from the dump on each lookup, so the reader should decode all
+/// instance fields and primitive array bodies, which is the default.
+pub struct Heap<T: Read + Seek, I: Identifier> {
+    reader: HprofReader<T, I>,
+    index: ObjectIndex,
+}
+
+impl<T: Read + Seek, I: Identifier> Heap<T, I> {
+    /// Uses the index that the reader builds while it reads the dump, which needs
+    /// [`crate::reader::ReaderOptions::index_objects`]. The rest of the dump is skipped over
+    /// to complete the index, see [`HprofReader::index_objects`].
+    pub fn new(mut reader: HprofReader<T, I>) -> Result<Heap<T, I>, Error> {
+        let index = reader.index_objects()?;
+        Ok(Self::with_index(reader, index))
+    }
+
+    /// Uses an index that was built from the same heap dump before.
+    pub fn with_index(reader: HprofReader<T, I>, index: ObjectIndex) -> Heap<T, I> {
+        Heap { reader, index }
+    }
+
+    pub fn reader(&mut self) -> &mut HprofReader<T, I> {
+        &mut self.reader
+    }
+
+    pub fn index(&self) -> &ObjectIndex {
+        &self.index
+    }
+
+    /// Returns the sub-record of the object with the given id, or `None` for null and for
+    /// ids that are not in the dump.
+    pub fn object(&mut self, object_id: ObjectId) -> Result<Option<HeapDumpTag>, Error> {
+        match self.index.offset_of(object_id) {
+            Some(offset) => self.reader.read_object_at(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the instance with the given id, or `None` if the object is not an instance.
+    pub fn instance(&mut self, object_id: ObjectId) -> Result<Option<Instance>, Error> {
+        match self.object(object_id)? {
+            Some(HeapDumpTag::HprofGcInstanceDump {
+                object_id,
+                class_object_id,
+                instance_field_values,
+                ..
+            }) => self
+                .reader
+                .resolve_instance(object_id, class_object_id, instance_field_values)
+                .map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the elements of the object array with the given id, or `None` if the object
+    /// is not an object array.
+    pub fn object_array(&mut self, object_id: ObjectId) -> Result<Option<Vec<ObjectId>>, Error> {
+        match self.object(object_id)? {
+            Some(HeapDumpTag::HprofGcObjArrayDump { elements, .. }) => Ok(Some(elements)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the elements of the primitive array with the given id, or `None` if the object
+    /// is not a primitive array.
+    pub fn primitive_array(
+        &mut self,
+        object_id: ObjectId,
+    ) -> Result<Option<PrimitiveArray>, Error> {
+        match self.object(object_id)? {
+            Some(HeapDumpTag::HprofGcPrimArrayDump { elements, .. }) => match elements {
+                Some(elements) => Ok(Some(elements)),
+                None => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "the reader skips primitive array bodies",
+                )),
+            },
+            _ => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use crate::hprof_model::{BasicType, ClassId, ClassInfo, HeapDumpTag, PrimitiveArray, Value};
+use crate::hprof_model::{Identifier, ObjectId, TraceSerial, U4, U8};
+use crate::reader::HprofReader;
+use crate::shallow_size::JvmLayout;
+use crate::visitor::{HprofVisitor, Visit};
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Read, Seek};
+
+/// Groups the objects of a histogram.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ClassKey {
+    /// Instances and object arrays, by the id of their class object.
+    Class(ClassId),
+    /// Primitive arrays, by their element type.
+    PrimitiveArray(BasicType),
+}
+
+impl ClassKey {
+    /// Returns the name of the class in JVM form, like `java/lang/String` or `[I` for
+    /// arrays of primitives. Class names are looked up in `reader`.
+    pub fn name<T: Read + Seek, I: Identifier>(
+        &self,
+        reader: &HprofReader<T, I>,
+    ) -> Result<Option<String>, Error> {
+        match *self {
+            ClassKey::Class(class_object_id) => {
+                Ok(reader.class_name(class_object_id)?.map(|n| n.into_owned()))
+            }
+            ClassKey::PrimitiveArray(element_type) => Ok(element_type
+                .is_primitive()
+                .then(|| format!("[{}", element_type.descriptor()))),
+        }
+    }
+}
+
+/// The number of objects of one class and the bytes they take up.
+#[derive(Clone, Debug)]
+pub struct HistogramEntry {
+    pub key: ClassKey,
+    /// The name from the `HPROF_LOAD_CLASS` record, in JVM form like `java/lang/String`.
+    pub name: Option<String>,
+    pub instances: U8,
+    /// The sum of the shallow sizes of the objects, see [`JvmLayout`].
+    pub bytes: U8,
+}
+
+/// Counts the objects per class while a heap dump is read with
+/// [`HprofReader::accept`], without keeping any objects.
+///
+/// Only the sizes of class dumps are kept, so instance field values need not be decoded.
+pub struct ClassHistogram {
+    layout: JvmLayout,
+    /// The superclass and the size of the own instance fields by class object id
+    classes: HashMap<ClassId, (ClassId, U8)>,
+    /// Instance counts, and the bytes of arrays
+    counts: HashMap<ClassKey, (U8, U8)>,
+    /// The classes of object arrays, which may have class dumps, too
+    array_classes: HashSet<ClassId>,
+}
+
+impl ClassHistogram {
+    /// Computes the sizes with the layout inferred from the identifier size.
+    pub fn new(identifier_size: U4) -> ClassHistogram {
+        Self::with_layout(JvmLayout::from_identifier_size(identifier_size))
+    }
+
+    pub fn with_layout(layout: JvmLayout) -> ClassHistogram {
+        ClassHistogram {
+            layout,
+            classes: HashMap::new(),
+            counts: HashMap::new(),
+            array_classes: HashSet::new(),
+        }
+    }
+
+    /// Returns the counts of all classes with objects, sorted by bytes, largest first.
+    /// The class names are looked up in `reader`.
+    pub fn entries<T: Read + Seek, I: Identifier>(
+        &self,
+        reader: &HprofReader<T, I>,
+    ) -> Result<Vec<HistogramEntry>, Error> {
+        let mut entries = self
+            .counts
+            .iter()
+            .map(|(&key, &(instances, bytes))| {
+                let name = key.name(reader)?;
+                let bytes = match key {
+                    ClassKey::Class(class_object_id)
+                        if !self.array_classes.contains(&class_object_id) =>
+                    {
+                        let fields_size = self.fields_size(class_object_id)?;
+                        instances * self.layout.instance_size(fields_size)
+                    }
+                    ClassKey::Class(_) => bytes,
+                    ClassKey::PrimitiveArray(_) => bytes,
+                };
+                Ok(HistogramEntry {
+                    key,
+                    name,
+                    instances,
+                    bytes,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        entries.sort_by_key(|e| std::cmp::Reverse(e.bytes));
+        Ok(entries)
+    }
+
+    /// The size of the instance fields on the heap.
+    fn fields_size(&self, class_object_id: ClassId) -> Result<U8, Error> {
+        let mut size = 0;
+        let mut class_id = class_object_id;
+        // a chain without cycles has at most one class per class dump
+        for _ in 0..=self.classes.len() {
+            if class_id.is_null() {
+                return Ok(size);
+            }
+            let &(super_class_id, fields_size) = self
+                .classes
+                .get(&class_id)
+                .ok_or(Error::other("missing class"))?;
+            size += fields_size;
+            class_id = super_class_id;
+        }
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "cyclic superclass chain",
+        ))
+    }
+}
+
+impl HprofVisitor for ClassHistogram {
+    fn visit_class_dump(&mut self, class: &ClassInfo) -> Visit {
+        let fields_size = class
+            .instance_fields
+            .iter()
+            .map(|f| self.layout.value_size(f.field_type) as U8)
+            .sum();
+        self.classes.insert(
+            class.class_object_id,
+            (class.super_class_object_id, fields_size),
+        );
+        Visit::Continue
+    }
+
+    fn visit_instance(
+        &mut self,
+        _: ObjectId,
+        _: TraceSerial,
+        class_object_id: ClassId,
+        _: &[Value],
+    ) -> Visit {
+        self.counts
+            .entry(ClassKey::Class(class_object_id))
+            .or_default()
+            .0 += 1;
+        Visit::Continue
+    }
+
+    fn visit_object_array(
+        &mut self,
+        _: ObjectId,
+        _: TraceSerial,
+        array_class_id: ClassId,
+        elements: &[ObjectId],
+    ) -> Visit {
+        self.array_classes.insert(array_class_id);
+        let count = self
+            .counts
+            .entry(ClassKey::Class(array_class_id))
+            .or_default();
+        count.0 += 1;
+        count.1 += self
+            .layout
+            .array_size(BasicType::Object, elements.len() as U8);
+        Visit::Continue
+    }
+
+    fn visit_primitive_array(
+        &mut self,
+        _: ObjectId,
+        _: TraceSerial,
+        element_type: BasicType,
+        element_count: U4,
+        _: Option<&PrimitiveArray>,
+    ) -> Visit {
+        let count = self
+            .counts
+            .entry(ClassKey::PrimitiveArray(element_type))
+            .or_default();
+        count.0 += 1;
+        count.1 += self.layout.array_size(element_type, element_count as U8);
+        Visit::Continue
+    }
+}
+
+/// What [`sample_histogram`] decides to decode or skip at random.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum SamplingUnit {
+    /// Whole heap dump segments. Skipped segments are only scanned for class dumps.
+    ///
+    /// Dumps often have only a few segments, which are too few to sample.
+    Segment,
+    /// Single instance and array dumps.
+    #[default]
+    SubRecord,
+}
+
+#[derive(Clone, Debug)]
+pub struct SamplingOptions {
+    pub unit: SamplingUnit,
+    /// The probability of each unit to be sampled, greater than 0 and at most 1.
+    pub rate: f64,
+    /// The seed of the random decisions, so that scans can be repeated.
+    pub seed: U8,
+    /// The layout for the object sizes, inferred from the identifier size if `None`.
+    pub layout: Option<JvmLayout>,
+}
+
+impl Default for SamplingOptions {
+    fn default() -> Self {
+        SamplingOptions {
+            unit: SamplingUnit::default(),
+            rate: 0.1,
+            seed: 0x5EED,
+            layout: None,
+        }
+    }
+}
+
+/// The extrapolated instance count and size of the objects of one class.
+///
+/// The errors are standard errors, so the real value is within two errors of the
+/// estimate with a probability of about 95%. They are infinite if fewer than two units
+/// were sampled out of more, since they cannot be estimated then.
+#[derive(Clone, Debug)]
+pub struct ClassEstimate {
+    pub key: ClassKey,
+    /// The name from the `HPROF_LOAD_CLASS` record, in JVM form like `java/lang/String`.
+    pub name: Option<String>,
+    pub instances: f64,
+    pub instances_error: f64,
+    /// The sum of the shallow sizes of the objects, see [`JvmLayout`].
+    pub bytes: f64,
+    pub bytes_error: f64,
+}
+
+/// The result of [`sample_histogram`].
+#[derive(Clone, Debug)]
+pub struct SampledHistogram {
+    /// Sorted by estimated bytes, largest first.
+    pub classes: Vec<ClassEstimate>,
+    pub total_bytes: f64,
+    pub total_bytes_error: f64,
+    pub sampled_units: U8,
+    pub total_units: U8,
+}
+
+/// Sums over the sampled units, and the sums of their squares for the error estimates.
+#[derive(Clone, Copy, Default)]
+struct Sums {
+    instances: f64,
+    instances_squared: f64,
+    bytes: f64,
+    bytes_squared: f64,
+}
+
+impl Sums {
+    fn add(&mut self, instances: f64, bytes: f64) {
+        self.instances += instances;
+        self.instances_squared += instances * instances;
+        self.bytes += bytes;
+        self.bytes_squared += bytes * bytes;
+    }
+}
+
+/// Reads the rest of the heap dump, but only decodes a random subset of the instance and
+/// array dumps, and extrapolates the instance counts and sizes of all classes from it.
+///
+/// All class dumps are read regardless of the sampling. For the fastest scan, create the
+/// reader with [`crate::reader::FieldDecoding::None`] and without primitive array bodies.
+pub fn sample_histogram<T: Read + Seek, I: Identifier>(
+    reader: &mut HprofReader<T, I>,
+    options: &SamplingOptions,
+) -> Result<SampledHistogram, Error> {
+    if !(options.rate > 0.0 && options.rate <= 1.0) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the sampling rate must be greater than 0 and at most 1",
+        ));
+    }
+    let layout = options
+        .layout
+        .unwrap_or_else(|| JvmLayout::from_identifier_size(reader.identifier_size()));
+    let mut random = SplitMix64(options.seed);
+    let mut sums: HashMap<ClassKey, Sums> = HashMap::new();
+    let mut total = Sums::default();
+    let mut sampled_units = 0;
+    let mut total_units = 0;
+    while let Some((tag, base)) = reader.read_record_header()? {
+        if tag != 0x1C {
+            // keeps the class names in the reader
+            reader.read_record(tag, base)?;
+            continue;
+        }
+        reader.start_heap_dump_segment(base)?;
+        let segment_sampled = match options.unit {
+            SamplingUnit::Segment => {
+                total_units += 1;
+                random.next_bool(options.rate)
+            }
+            SamplingUnit::SubRecord => true,
+        };
+        sampled_units += (options.unit == SamplingUnit::Segment && segment_sampled) as U8;
+        // the totals of the current segment
+        let mut segment: HashMap<ClassKey, (f64, f64)> = HashMap::new();
+        while reader.has_sub_records()? {
+            let (_, tag, id) = reader.read_sub_record_header()?;
+            let decode = match tag {
+                0x20 => true,
+                0x21..=0x23 if segment_sampled => match options.unit {
+                    SamplingUnit::Segment => true,
+                    SamplingUnit::SubRecord => {
+                        total_units += 1;
+                        random.next_bool(options.rate)
+                    }
+                },
+                _ => false,
+            };
+            let Some(sub_record) = reader.read_sub_record_body(tag, id, decode)? else {
+                continue;
+            };
+            let Some((key, bytes)) = object_size(reader, &layout, &sub_record)? else {
+                continue; // a class dump
+            };
+            match options.unit {
+                SamplingUnit::Segment => {
+                    let totals = segment.entry(key).or_default();
+                    totals.0 += 1.0;
+                    totals.1 += bytes as f64;
+                }
+                SamplingUnit::SubRecord => {
+                    sampled_units += 1;
+                    sums.entry(key).or_default().add(1.0, bytes as f64);
+                    total.add(1.0, bytes as f64);
+                }
+            }
+        }
+        if !segment.is_empty() {
+            let mut segment_bytes = 0.0;
+            for (key, (instances, bytes)) in segment {
+                sums.entry(key).or_default().add(instances, bytes);
+                segment_bytes += bytes;
+            }
+            total.add(0.0, segment_bytes);
+        }
+    }
+
+    // Horvitz-Thompson estimates for Bernoulli sampling of the units
+    let rate = options.rate;
+    let estimate = |sum: f64| sum / rate;
+    let error = |sum_squared: f64| {
+        if sampled_units < 2 && sampled_units < total_units {
+            f64::INFINITY
+        } else {
+            ((1.0 - rate) * sum_squared).sqrt() / rate
+        }
+    };
+    let mut classes = sums
+        .into_iter()
+        .map(|(key, sums)| {
+            let name = key.name(reader)?;
+            Ok(ClassEstimate {
+                key,
+                name,
+                instances: estimate(sums.instances),
+                instances_error: error(sums.instances_squared),
+                bytes: estimate(sums.bytes),
+                bytes_error: error(sums.bytes_squared),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    classes.sort_by(|a, b| b.bytes.total_cmp(&a.bytes));
+    Ok(SampledHistogram {
+        classes,
+        total_bytes: estimate(total.bytes),
+        total_bytes_error: error(total.bytes_squared),
+        sampled_units,
+        total_units,
+    })
+}
+
+/// Returns the histogram key and the shallow size of the object of an instance or array dump.
+fn object_size<T: Read + Seek, I: Identifier>(
+    reader: &mut HprofReader<T, I>,
+    layout: &JvmLayout,
+    sub_record: &HeapDumpTag,
+) -> Result<Option<(ClassKey, U8)>, Error> {
+    let key = match sub_record {
+        HeapDumpTag::HprofGcInstanceDump {
+            class_object_id, ..
+        } => ClassKey::Class(*class_object_id),
+        HeapDumpTag::HprofGcObjArrayDump { array_class_id, .. } => ClassKey::Class(*array_class_id),
+        HeapDumpTag::HprofGcPrimArrayDump { element_type, .. } => {
+            ClassKey::PrimitiveArray(*element_type)
+        }
+        _ => return Ok(None),
+    };
+    Ok(layout
+        .shallow_size(reader, sub_record)?
+        .map(|size| (key, size)))
+}
+
+/// A small and fast pseudo-random number generator, see <https://prng.di.unimi.it/splitmix64.c>.
+struct SplitMix64(U8);
+
+impl SplitMix64 {
+    fn next(&mut self) -> U8 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns `true` with the given probability.
+    fn next_bool(&mut self, probability: f64) -> bool {
+        // the upper 53 bits make a uniformly distributed double in [0, 1)
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
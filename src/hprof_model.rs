@@ -1,36 +1,51 @@
+use std::io::{Error, ErrorKind, Read};
+use std::sync::Arc;
 use strum_macros;
-use strum_macros::Display;
+use strum_macros::{Display, EnumDiscriminants};
 
-// top-level tags
-#[derive(Display)]
+/// A top-level record.
+///
+/// With the `serde` feature, records are serialized as maps of their fields with an
+/// additional `tag` entry that holds the name of the record in the HPROF format,
+/// like `{"tag": "HPROF_LOAD_CLASS", "class_serial_number": 1, ...}`.
+/// Ids and serial numbers are serialized as plain numbers. The representations need a
+/// self-describing format that writes structs as maps, like JSON or MessagePack written
+/// with `rmp_serde::to_vec_named`.
+#[derive(Display, EnumDiscriminants)]
+#[strum_discriminants(name(RecordKind), derive(Display, Hash))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "tag", rename_all = "SCREAMING_SNAKE_CASE")
+)]
 pub enum RecordTag {
     HprofUtf8 {
         base: RecordBase,
-        id: U8,
-        utf8: String,
+        id: StringId,
+        utf8: Arc<str>,
     },
     HprofLoadClass {
         base: RecordBase,
-        class_serial_number: U4,
-        class_object_id: U8,
-        stack_trace_serial_number: U4,
-        class_name_id: U8,
+        class_serial_number: ClassSerial,
+        class_object_id: ClassId,
+        stack_trace_serial_number: TraceSerial,
+        class_name_id: StringId,
     },
     HprofUnloadClass,
     HprofFrame {
         base: RecordBase,
-        stack_frame_id: U8,
-        method_name_id: U8,
-        method_signature_id: U8,
-        source_file_name_id: U8,
-        class_serial_numer: U4,
+        stack_frame_id: FrameId,
+        method_name_id: StringId,
+        method_signature_id: StringId,
+        source_file_name_id: StringId,
+        class_serial_number: ClassSerial,
         line_number: I4,
     },
     HprofTrace {
         base: RecordBase,
-        stack_trace_serial_number: U4,
-        thread_serial_number: U4,
-        stack_frame_ids: Vec<U8>,
+        stack_trace_serial_number: TraceSerial,
+        thread_serial_number: ThreadSerial,
+        stack_frame_ids: Vec<FrameId>,
     },
     HprofAllocSites,
     HprofStartThread,
@@ -46,93 +61,169 @@ pub enum RecordTag {
     HprofHeapDumpEnd,
 }
 
-// sub-record tags in HPROF_HEAP_DUMP and HPROF_HEAP_DUMP_SEGMENT
+/// A sub-record of `HPROF_HEAP_DUMP` and `HPROF_HEAP_DUMP_SEGMENT` records.
+///
+/// With the `serde` feature, sub-records are serialized like [`RecordTag`], with tags like
+/// `HPROF_GC_INSTANCE_DUMP`. The fields of class dumps are inlined next to the tag.
+#[derive(EnumDiscriminants)]
+#[strum_discriminants(name(SubRecordKind), derive(Display, Hash))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "tag", rename_all = "SCREAMING_SNAKE_CASE")
+)]
 pub enum HeapDumpTag {
-    HprofGcRootUnknown,
+    HprofGcRootUnknown {
+        object_id: ObjectId,
+    },
     HprofGcRootThreadObj {
-        thread_object_id: U8,
-        thread_sequence_number: U4,
-        stack_trace_sequence_number: U4,
+        thread_object_id: ObjectId,
+        thread_sequence_number: ThreadSerial,
+        stack_trace_sequence_number: TraceSerial,
     },
     HprofGcRootJniGlobal {
-        object_id: U8,
-        jni_global_ref_id: U8,
+        object_id: ObjectId,
+        jni_global_ref_id: ObjectId,
     },
     HprofGcRootJniLocal {
-        object_id: U8,
-        thread_serial_number: U4,
+        object_id: ObjectId,
+        thread_serial_number: ThreadSerial,
         frame_number: U4, // "frame # in stack trace (-1 for empty)" ...???
     },
     HprofGcRootJavaFrame {
-        object_id: U8,
-        thread_serial_number: U4,
+        object_id: ObjectId,
+        thread_serial_number: ThreadSerial,
         frame_number: U4, // "frame # in stack trace (-1 for empty)" ...???
     },
-    HprofGcRootNativeStack,
+    HprofGcRootNativeStack {
+        object_id: ObjectId,
+        thread_serial_number: ThreadSerial,
+    },
     HprofGcRootStickyClass {
-        object_id: U8,
+        object_id: ObjectId,
+    },
+    HprofGcRootThreadBlock {
+        object_id: ObjectId,
+        thread_serial_number: ThreadSerial,
     },
-    HprofGcRootThreadBlock,
-    HprofGcRootMonitorUsed,
-    HprofGcClassDump(ClassInfo),
+    HprofGcRootMonitorUsed {
+        object_id: ObjectId,
+    },
+    HprofGcClassDump(Arc<ClassInfo>),
     HprofGcInstanceDump {
-        object_id: U8,
-        stack_trace_serial_number: U4,
-        class_object_id: U8,
+        object_id: ObjectId,
+        stack_trace_serial_number: TraceSerial,
+        class_object_id: ClassId,
         instance_field_values: Vec<Value>,
     },
     HprofGcObjArrayDump {
-        array_object_id: U8,
-        stack_trace_serial_number: U4,
-        array_class_id: U8,
-        elements: Vec<U8>,
+        array_object_id: ObjectId,
+        stack_trace_serial_number: TraceSerial,
+        array_class_id: ClassId,
+        elements: Vec<ObjectId>,
     },
     HprofGcPrimArrayDump {
-        array_object_id: U8,
-        stack_trace_serial_number: U4,
-        elements: Vec<Value>, // TODO only one type is valid here, can we represent that?
+        array_object_id: ObjectId,
+        stack_trace_serial_number: TraceSerial,
+        element_type: BasicType,
+        element_count: U4,
+        /// `None` if the array body was skipped, see [`crate::reader::ReaderOptions`].
+        elements: Option<PrimitiveArray>,
     },
 }
 
 impl RecordTag {
+    pub fn id(&self) -> U1 {
+        RecordKind::from(self).id()
+    }
+}
+
+impl RecordKind {
     pub fn id(&self) -> U1 {
         match self {
-            RecordTag::HprofUtf8 { .. } => 0x01,
-            RecordTag::HprofLoadClass { .. } => 0x02,
-            RecordTag::HprofUnloadClass => 0x3,
-            RecordTag::HprofFrame { .. } => 0x04,
-            RecordTag::HprofTrace { .. } => 0x05,
-            RecordTag::HprofAllocSites => 0x06,
-            RecordTag::HprofHeapSummary => 0x07,
-            RecordTag::HprofStartThread => 0x0A,
-            RecordTag::HprofEndThread => 0x0B,
-            RecordTag::HprofHeapDump => 0x0C,
-            RecordTag::HprofCpuSamples => 0x0D,
-            RecordTag::HprofControlSettings => 0x0E,
-            RecordTag::HprofHeapDumpSegment { .. } => 0x1C,
-            RecordTag::HprofHeapDumpEnd => 0x2C,
+            RecordKind::HprofUtf8 => 0x01,
+            RecordKind::HprofLoadClass => 0x02,
+            RecordKind::HprofUnloadClass => 0x3,
+            RecordKind::HprofFrame => 0x04,
+            RecordKind::HprofTrace => 0x05,
+            RecordKind::HprofAllocSites => 0x06,
+            RecordKind::HprofHeapSummary => 0x07,
+            RecordKind::HprofStartThread => 0x0A,
+            RecordKind::HprofEndThread => 0x0B,
+            RecordKind::HprofHeapDump => 0x0C,
+            RecordKind::HprofCpuSamples => 0x0D,
+            RecordKind::HprofControlSettings => 0x0E,
+            RecordKind::HprofHeapDumpSegment => 0x1C,
+            RecordKind::HprofHeapDumpEnd => 0x2C,
         }
     }
+
+    pub fn from_id(id: U1) -> Option<RecordKind> {
+        let kind = match id {
+            0x01 => RecordKind::HprofUtf8,
+            0x02 => RecordKind::HprofLoadClass,
+            0x03 => RecordKind::HprofUnloadClass,
+            0x04 => RecordKind::HprofFrame,
+            0x05 => RecordKind::HprofTrace,
+            0x06 => RecordKind::HprofAllocSites,
+            0x07 => RecordKind::HprofHeapSummary,
+            0x0A => RecordKind::HprofStartThread,
+            0x0B => RecordKind::HprofEndThread,
+            0x0C => RecordKind::HprofHeapDump,
+            0x0D => RecordKind::HprofCpuSamples,
+            0x0E => RecordKind::HprofControlSettings,
+            0x1C => RecordKind::HprofHeapDumpSegment,
+            0x2C => RecordKind::HprofHeapDumpEnd,
+            _ => return None,
+        };
+        Some(kind)
+    }
 }
 
 impl HeapDumpTag {
+    pub fn id(&self) -> U1 {
+        SubRecordKind::from(self).id()
+    }
+}
+
+impl SubRecordKind {
     pub fn id(&self) -> U1 {
         match self {
-            HeapDumpTag::HprofGcRootUnknown => 0xFF,
-            HeapDumpTag::HprofGcRootJniGlobal { .. } => 0x01,
-            HeapDumpTag::HprofGcRootJniLocal { .. } => 0x02,
-            HeapDumpTag::HprofGcRootJavaFrame { .. } => 0x03,
-            HeapDumpTag::HprofGcRootNativeStack => 0x04,
-            HeapDumpTag::HprofGcRootStickyClass { .. } => 0x05,
-            HeapDumpTag::HprofGcRootThreadBlock => 0x06,
-            HeapDumpTag::HprofGcRootMonitorUsed => 0x07,
-            HeapDumpTag::HprofGcRootThreadObj { .. } => 0x08,
-            HeapDumpTag::HprofGcClassDump(_) => 0x20,
-            HeapDumpTag::HprofGcInstanceDump { .. } => 0x21,
-            HeapDumpTag::HprofGcObjArrayDump { .. } => 0x22,
-            HeapDumpTag::HprofGcPrimArrayDump { .. } => 0x23,
+            SubRecordKind::HprofGcRootUnknown => 0xFF,
+            SubRecordKind::HprofGcRootJniGlobal => 0x01,
+            SubRecordKind::HprofGcRootJniLocal => 0x02,
+            SubRecordKind::HprofGcRootJavaFrame => 0x03,
+            SubRecordKind::HprofGcRootNativeStack => 0x04,
+            SubRecordKind::HprofGcRootStickyClass => 0x05,
+            SubRecordKind::HprofGcRootThreadBlock => 0x06,
+            SubRecordKind::HprofGcRootMonitorUsed => 0x07,
+            SubRecordKind::HprofGcRootThreadObj => 0x08,
+            SubRecordKind::HprofGcClassDump => 0x20,
+            SubRecordKind::HprofGcInstanceDump => 0x21,
+            SubRecordKind::HprofGcObjArrayDump => 0x22,
+            SubRecordKind::HprofGcPrimArrayDump => 0x23,
         }
     }
+
+    pub fn from_id(id: U1) -> Option<SubRecordKind> {
+        let kind = match id {
+            0xFF => SubRecordKind::HprofGcRootUnknown,
+            0x01 => SubRecordKind::HprofGcRootJniGlobal,
+            0x02 => SubRecordKind::HprofGcRootJniLocal,
+            0x03 => SubRecordKind::HprofGcRootJavaFrame,
+            0x04 => SubRecordKind::HprofGcRootNativeStack,
+            0x05 => SubRecordKind::HprofGcRootStickyClass,
+            0x06 => SubRecordKind::HprofGcRootThreadBlock,
+            0x07 => SubRecordKind::HprofGcRootMonitorUsed,
+            0x08 => SubRecordKind::HprofGcRootThreadObj,
+            0x20 => SubRecordKind::HprofGcClassDump,
+            0x21 => SubRecordKind::HprofGcInstanceDump,
+            0x22 => SubRecordKind::HprofGcObjArrayDump,
+            0x23 => SubRecordKind::HprofGcPrimArrayDump,
+            _ => return None,
+        };
+        Some(kind)
+    }
 }
 
 pub type U1 = u8;
@@ -140,48 +231,293 @@ pub type U2 = u16;
 pub type U4 = u32;
 pub type I4 = i32;
 pub type U8 = u64;
+macro_rules! define_id {
+    ($(#[$doc:meta])* $name:ident($type:ident), $format:literal) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+        #[cfg_attr(
+            feature = "serde",
+            derive(serde::Serialize, serde::Deserialize),
+            serde(transparent)
+        )]
+        pub struct $name(pub $type);
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, $format, self.0)
+            }
+        }
+    };
+}
+
+define_id!(
+    /// The id of an object, which is its address in most dumps. `0` is the null reference.
+    ObjectId(U8),
+    "{:#x}"
+);
+define_id!(
+    /// The object id of a class. `0` is the null reference.
+    ClassId(U8),
+    "{:#x}"
+);
+define_id!(
+    /// The id of a string of a `HPROF_UTF8` record.
+    StringId(U8),
+    "{:#x}"
+);
+define_id!(
+    /// The id of a stack frame of a `HPROF_FRAME` record.
+    FrameId(U8),
+    "{:#x}"
+);
+define_id!(
+    /// The serial number of a class of a `HPROF_LOAD_CLASS` record.
+    ClassSerial(U4),
+    "{}"
+);
+define_id!(
+    /// The serial number of a thread.
+    ThreadSerial(U4),
+    "{}"
+);
+define_id!(
+    /// The serial number of a stack trace of a `HPROF_TRACE` record.
+    TraceSerial(U4),
+    "{}"
+);
+
+impl ObjectId {
+    pub fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl ClassId {
+    pub fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// Classes are objects, too.
+impl From<ClassId> for ObjectId {
+    fn from(id: ClassId) -> Self {
+        ObjectId(id.0)
+    }
+}
+
+/// The width of the identifiers in a heap dump, as declared in its header.
 pub trait Identifier {
     fn size() -> usize;
+
+    /// Reads an identifier and widens it to [`U8`].
+    fn read(input: &mut impl Read) -> Result<U8, Error>;
+
+    /// Decodes the identifier at the start of `bytes` and widens it to [`U8`].
+    fn decode(bytes: &[u8]) -> U8;
 }
 impl Identifier for U4 {
     fn size() -> usize {
         (Self::BITS >> 3) as usize
     }
+
+    fn read(input: &mut impl Read) -> Result<U8, Error> {
+        let mut buf = [0; 4];
+        input.read_exact(&mut buf)?;
+        Ok(U4::from_be_bytes(buf) as U8)
+    }
+
+    fn decode(bytes: &[u8]) -> U8 {
+        U4::from_be_bytes(bytes[..4].try_into().unwrap()) as U8
+    }
 }
 
 impl Identifier for U8 {
     fn size() -> usize {
         (Self::BITS >> 3) as usize
     }
+
+    fn read(input: &mut impl Read) -> Result<U8, Error> {
+        let mut buf = [0; 8];
+        input.read_exact(&mut buf)?;
+        Ok(U8::from_be_bytes(buf))
+    }
+
+    fn decode(bytes: &[u8]) -> U8 {
+        U8::from_be_bytes(bytes[..8].try_into().unwrap())
+    }
 }
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RecordBase {
-    pub(crate) micros_since: U4,
-    pub(crate) size_remaining: U4,
+    pub micros_since: U4,
+    pub size_remaining: U4,
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClassInfo {
-    pub class_object_id: U8,
-    pub stack_trace_serial_number: U4,
-    pub super_class_object_id: U8,
-    pub class_loader_object_id: U8,
-    pub signers_object_id: U8,
-    pub protection_domain_object_id: U8,
+    pub class_object_id: ClassId,
+    pub stack_trace_serial_number: TraceSerial,
+    /// Null for `java.lang.Object`.
+    pub super_class_object_id: ClassId,
+    pub class_loader_object_id: ObjectId,
+    pub signers_object_id: ObjectId,
+    pub protection_domain_object_id: ObjectId,
     pub instance_size: U4,
     pub static_fields: Vec<FieldInfo>,
     pub instance_fields: Vec<FieldInfo>,
 }
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FieldInfo {
-    pub name_id: U8,
-    pub type_tag: U1,
+    pub name_id: StringId,
+    pub field_type: BasicType,
     pub value: Option<Value>,
 }
 
-#[derive(Clone)]
+/// The type of a field or of the elements of an array.
+///
+/// With the `serde` feature, types are serialized as their lowercase name, like `"int"`
+/// or `"object"`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum BasicType {
+    /// A reference to an object or array.
+    Object,
+    Boolean,
+    Char,
+    Float,
+    Double,
+    Byte,
+    Short,
+    Int,
+    Long,
+}
+
+impl BasicType {
+    /// The size of a value of this type in the dump.
+    pub fn size(&self, identifier_size: U4) -> U4 {
+        match self {
+            BasicType::Object => identifier_size,
+            BasicType::Boolean | BasicType::Byte => 1,
+            BasicType::Char | BasicType::Short => 2,
+            BasicType::Float | BasicType::Int => 4,
+            BasicType::Double | BasicType::Long => 8,
+        }
+    }
+
+    /// The character of this type in Java type descriptors, `L` for objects.
+    pub fn descriptor(&self) -> char {
+        match self {
+            BasicType::Object => 'L',
+            BasicType::Boolean => 'Z',
+            BasicType::Char => 'C',
+            BasicType::Float => 'F',
+            BasicType::Double => 'D',
+            BasicType::Byte => 'B',
+            BasicType::Short => 'S',
+            BasicType::Int => 'I',
+            BasicType::Long => 'J',
+        }
+    }
+
+    /// Returns the type with the given descriptor character.
+    pub fn from_descriptor(descriptor: char) -> Option<BasicType> {
+        let basic_type = match descriptor {
+            'L' => BasicType::Object,
+            'Z' => BasicType::Boolean,
+            'C' => BasicType::Char,
+            'F' => BasicType::Float,
+            'D' => BasicType::Double,
+            'B' => BasicType::Byte,
+            'S' => BasicType::Short,
+            'I' => BasicType::Int,
+            'J' => BasicType::Long,
+            _ => return None,
+        };
+        Some(basic_type)
+    }
+
+    /// The Java keyword of a primitive type, like `int`.
+    pub fn keyword(&self) -> Option<&'static str> {
+        let keyword = match self {
+            BasicType::Object => return None,
+            BasicType::Boolean => "boolean",
+            BasicType::Char => "char",
+            BasicType::Float => "float",
+            BasicType::Double => "double",
+            BasicType::Byte => "byte",
+            BasicType::Short => "short",
+            BasicType::Int => "int",
+            BasicType::Long => "long",
+        };
+        Some(keyword)
+    }
+
+    /// The tag of this type in the dump.
+    pub fn tag(&self) -> U1 {
+        match self {
+            BasicType::Object => 0x02,
+            BasicType::Boolean => 0x04,
+            BasicType::Char => 0x05,
+            BasicType::Float => 0x06,
+            BasicType::Double => 0x07,
+            BasicType::Byte => 0x08,
+            BasicType::Short => 0x09,
+            BasicType::Int => 0x0A,
+            BasicType::Long => 0x0B,
+        }
+    }
+
+    pub fn is_primitive(&self) -> bool {
+        *self != BasicType::Object
+    }
+}
+
+/// Converts a type tag of the dump. Both the array (`0x01`) and the object (`0x02`) tag
+/// are [`BasicType::Object`].
+impl TryFrom<U1> for BasicType {
+    type Error = Error;
+
+    fn try_from(tag: U1) -> Result<Self, Self::Error> {
+        let basic_type = match tag {
+            0x01 | 0x02 => BasicType::Object,
+            0x04 => BasicType::Boolean,
+            0x05 => BasicType::Char,
+            0x06 => BasicType::Float,
+            0x07 => BasicType::Double,
+            0x08 => BasicType::Byte,
+            0x09 => BasicType::Short,
+            0x0A => BasicType::Int,
+            0x0B => BasicType::Long,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unsupported type tag {tag:#x}"),
+                ))
+            }
+        };
+        Ok(basic_type)
+    }
+}
+
+/// A field value.
+///
+/// With the `serde` feature, values are serialized with their [`BasicType`], like
+/// `{"type": "int", "value": 42}` or `{"type": "object", "value": {"object_id": 4096}}`.
+/// JSON has no NaN or infinite numbers, so use a format like MessagePack to keep those.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", content = "value", rename_all = "lowercase")
+)]
 pub enum Value {
-    Object { object_id: U8 },
-    Array { object_id: U8 },
+    Object { object_id: ObjectId },
     Byte(i8),
     Char(u16),
     Short(i16),
@@ -191,3 +527,101 @@ pub enum Value {
     Long(i64),
     Boolean(bool),
 }
+
+macro_rules! define_value_as {
+    ($name:ident, $variant:ident, $type:ty) => {
+        /// Returns the value if it has this type.
+        pub fn $name(&self) -> Option<$type> {
+            match self {
+                Value::$variant(v) => Some(*v),
+                _ => None,
+            }
+        }
+    };
+}
+
+impl Value {
+    /// Returns the referenced object if this is a reference, which may be null.
+    pub fn as_object(&self) -> Option<ObjectId> {
+        match self {
+            Value::Object { object_id } => Some(*object_id),
+            _ => None,
+        }
+    }
+
+    define_value_as!(as_boolean, Boolean, bool);
+    define_value_as!(as_char, Char, u16);
+    define_value_as!(as_byte, Byte, i8);
+    define_value_as!(as_short, Short, i16);
+    define_value_as!(as_int, Int, i32);
+    define_value_as!(as_long, Long, i64);
+    define_value_as!(as_float, Float, f32);
+    define_value_as!(as_double, Double, f64);
+}
+
+/// The elements of a primitive array, stored with their element type.
+///
+/// With the `serde` feature, arrays are serialized like `{"type": "int", "values": [1, 2]}`.
+/// Chars are serialized as numbers, since they may be halves of surrogate pairs.
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", content = "values", rename_all = "lowercase")
+)]
+pub enum PrimitiveArray {
+    Boolean(Vec<bool>),
+    Char(Vec<u16>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    Byte(Vec<i8>),
+    Short(Vec<i16>),
+    Int(Vec<i32>),
+    Long(Vec<i64>),
+}
+
+impl PrimitiveArray {
+    pub fn element_type(&self) -> BasicType {
+        match self {
+            PrimitiveArray::Boolean(_) => BasicType::Boolean,
+            PrimitiveArray::Char(_) => BasicType::Char,
+            PrimitiveArray::Float(_) => BasicType::Float,
+            PrimitiveArray::Double(_) => BasicType::Double,
+            PrimitiveArray::Byte(_) => BasicType::Byte,
+            PrimitiveArray::Short(_) => BasicType::Short,
+            PrimitiveArray::Int(_) => BasicType::Int,
+            PrimitiveArray::Long(_) => BasicType::Long,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            PrimitiveArray::Boolean(v) => v.len(),
+            PrimitiveArray::Char(v) => v.len(),
+            PrimitiveArray::Float(v) => v.len(),
+            PrimitiveArray::Double(v) => v.len(),
+            PrimitiveArray::Byte(v) => v.len(),
+            PrimitiveArray::Short(v) => v.len(),
+            PrimitiveArray::Int(v) => v.len(),
+            PrimitiveArray::Long(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the element at `index` as a [`Value`].
+    pub fn get(&self, index: usize) -> Option<Value> {
+        match self {
+            PrimitiveArray::Boolean(v) => v.get(index).map(|&e| Value::Boolean(e)),
+            PrimitiveArray::Char(v) => v.get(index).map(|&e| Value::Char(e)),
+            PrimitiveArray::Float(v) => v.get(index).map(|&e| Value::Float(e)),
+            PrimitiveArray::Double(v) => v.get(index).map(|&e| Value::Double(e)),
+            PrimitiveArray::Byte(v) => v.get(index).map(|&e| Value::Byte(e)),
+            PrimitiveArray::Short(v) => v.get(index).map(|&e| Value::Short(e)),
+            PrimitiveArray::Int(v) => v.get(index).map(|&e| Value::Int(e)),
+            PrimitiveArray::Long(v) => v.get(index).map(|&e| Value::Long(e)),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::hprof_model::{ClassId, ObjectId, Value};
+use crate::layout::ClassLayout;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+/// An instance dump whose field values are paired with the fields of its class.
+///
+/// Created with [`crate::reader::HprofReader::resolve_instance`].
+#[derive(Clone, Debug)]
+pub struct Instance {
+    object_id: ObjectId,
+    layout: Arc<ClassLayout>,
+    values: Vec<Value>,
+}
+
+impl Instance {
+    /// Fails unless there is exactly one value per field of the layout, which is the case
+    /// when all field values were decoded.
+    pub fn new(
+        object_id: ObjectId,
+        layout: Arc<ClassLayout>,
+        values: Vec<Value>,
+    ) -> Result<Instance, Error> {
+        if layout.fields.len() != values.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the field values do not match the class layout",
+            ));
+        }
+        Ok(Instance {
+            object_id,
+            layout,
+            values,
+        })
+    }
+
+    pub fn object_id(&self) -> ObjectId {
+        self.object_id
+    }
+
+    pub fn class_object_id(&self) -> ClassId {
+        self.layout.class_object_id
+    }
+
+    pub fn layout(&self) -> &ClassLayout {
+        &self.layout
+    }
+
+    /// The field values in layout order.
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    /// Returns the declaring class, the name and the value of each field. The fields of
+    /// a class come before the fields of its superclass. The name is `None` if it was not
+    /// read, see [`crate::layout::LayoutField::name`].
+    pub fn fields(&self) -> impl Iterator<Item = (ClassId, Option<&str>, &Value)> {
+        self.layout
+            .fields
+            .iter()
+            .zip(&self.values)
+            .map(|(f, v)| (f.declaring_class_id, f.name.as_deref(), v))
+    }
+
+    /// Returns the value of the field with the given name. If a field shadows a field of
+    /// a superclass, the value of the subclass field is returned.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.layout
+            .field_index_by_name(name)
+            .map(|i| &self.values[i])
+    }
+
+    /// Returns the value of the field with the given name that is declared by the given class,
+    /// which also finds fields that are shadowed by a subclass.
+    pub fn get_declared(&self, declaring_class_id: ClassId, name: &str) -> Option<&Value> {
+        self.fields()
+            .find(|&(class_id, n, _)| class_id == declaring_class_id && n == Some(name))
+            .map(|(_, _, v)| v)
+    }
+
+    /// Returns the object referenced by the field, which may be null. `None` if there is
+    /// no such field or it is primitive.
+    pub fn get_ref(&self, name: &str) -> Option<ObjectId> {
+        self.get(name).and_then(Value::as_object)
+    }
+
+    pub fn get_boolean(&self, name: &str) -> Option<bool> {
+        self.get(name).and_then(Value::as_boolean)
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i32> {
+        self.get(name).and_then(Value::as_int)
+    }
+
+    pub fn get_long(&self, name: &str) -> Option<i64> {
+        self.get(name).and_then(Value::as_long)
+    }
+}
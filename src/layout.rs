@@ -0,0 +1,187 @@
+use crate::hprof_model::{
+    BasicType, ClassId, ClassInfo, Identifier, ObjectId, StringId, Value, U4,
+};
+use crate::storage::Table;
+use std::collections::HashMap;
+use std::io::Error;
+use std::sync::Arc;
+
+/// An instance field of a class or of one of its superclasses.
+#[derive(Clone, Debug)]
+pub struct LayoutField {
+    /// The class that declares the field.
+    pub declaring_class_id: ClassId,
+    pub name_id: StringId,
+    /// The resolved name, or `None` if the `HPROF_UTF8` record of the name was not read.
+    /// It is looked up once per declaring class and shared by the layouts of its subclasses.
+    pub name: Option<Arc<str>>,
+    pub field_type: BasicType,
+    /// The offset of the value in the field data of an instance dump.
+    pub offset: U4,
+}
+
+/// All instance fields of a class, including the inherited ones, in the order
+/// in which their values appear in instance dumps.
+#[derive(Clone, Debug)]
+pub struct ClassLayout {
+    pub class_object_id: ClassId,
+    pub fields: Vec<LayoutField>,
+    /// The size of the field data of an instance dump.
+    pub size: U4,
+}
+
+impl ClassLayout {
+    /// Computes the layout of `class` from its own fields and the layout of its superclass,
+    /// which is `None` for classes without a superclass.
+    pub(crate) fn compute(
+        class: &ClassInfo,
+        super_layout: Option<&ClassLayout>,
+        name_cache: &dyn Table<str>,
+        identifier_size: U4,
+    ) -> Result<ClassLayout, Error> {
+        let super_fields = super_layout.map_or(&[][..], |layout| &layout.fields);
+        let mut fields = Vec::with_capacity(class.instance_fields.len() + super_fields.len());
+        let mut offset = 0;
+        // the fields of a class come before the fields of its superclass
+        for field in &class.instance_fields {
+            fields.push(LayoutField {
+                declaring_class_id: class.class_object_id,
+                name_id: field.name_id,
+                name: name_cache.get(field.name_id.0)?.map(Arc::from),
+                field_type: field.field_type,
+                offset,
+            });
+            offset += field.field_type.size(identifier_size);
+        }
+        fields.extend(super_fields.iter().map(|field| LayoutField {
+            offset: offset + field.offset,
+            ..field.clone()
+        }));
+        Ok(ClassLayout {
+            class_object_id: class.class_object_id,
+            fields,
+            size: offset + super_layout.map_or(0, |layout| layout.size),
+        })
+    }
+
+    /// Returns the index of the field with the given name, which is also the index of its
+    /// value in fully decoded instance dumps. If a field shadows a field of a superclass,
+    /// the field of the subclass is returned.
+    pub fn field_index(&self, name_id: StringId) -> Option<usize> {
+        self.fields.iter().position(|f| f.name_id == name_id)
+    }
+
+    /// Like [`ClassLayout::field_index`], but looks the field up by its name.
+    pub fn field_index_by_name(&self, name: &str) -> Option<usize> {
+        self.fields
+            .iter()
+            .position(|f| f.name.as_deref() == Some(name))
+    }
+}
+
+/// Caches class layouts. Once `capacity` layouts are cached, the CLOCK algorithm evicts one
+/// that was not used since the last sweep over the cache.
+pub(crate) struct LayoutCache {
+    /// `None` if the cache is not bounded
+    capacity: Option<usize>,
+    /// The cached layouts, and whether each one was used since the hand passed it
+    slots: Vec<(Arc<ClassLayout>, bool)>,
+    /// The slots by class object id
+    index: HashMap<ClassId, usize>,
+    /// The next slot to consider for eviction
+    hand: usize,
+}
+
+impl LayoutCache {
+    pub(crate) fn new(capacity: Option<usize>) -> LayoutCache {
+        LayoutCache {
+            capacity,
+            slots: vec![],
+            index: HashMap::new(),
+            hand: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, class_object_id: ClassId) -> Option<Arc<ClassLayout>> {
+        let &slot = self.index.get(&class_object_id)?;
+        let (layout, used) = &mut self.slots[slot];
+        *used = true;
+        Some(layout.clone())
+    }
+
+    pub(crate) fn insert(&mut self, layout: Arc<ClassLayout>) {
+        let class_object_id = layout.class_object_id;
+        if let Some(&slot) = self.index.get(&class_object_id) {
+            self.slots[slot] = (layout, true);
+            return;
+        }
+        if self
+            .capacity
+            .is_none_or(|capacity| self.slots.len() < capacity)
+        {
+            self.index.insert(class_object_id, self.slots.len());
+            self.slots.push((layout, false));
+            return;
+        }
+        // gives used layouts a second chance
+        while self.slots[self.hand].1 {
+            self.slots[self.hand].1 = false;
+            self.hand = (self.hand + 1) % self.slots.len();
+        }
+        let evicted = &self.slots[self.hand].0;
+        self.index.remove(&evicted.class_object_id);
+        self.index.insert(class_object_id, self.hand);
+        self.slots[self.hand] = (layout, false);
+        self.hand = (self.hand + 1) % self.slots.len();
+    }
+}
+
+pub(crate) fn decode_value<I: Identifier>(bytes: &[u8], basic_type: BasicType) -> Value {
+    fn be<const N: usize>(bytes: &[u8]) -> [u8; N] {
+        bytes[..N].try_into().unwrap()
+    }
+    match basic_type {
+        BasicType::Object => Value::Object {
+            object_id: ObjectId(I::decode(bytes)),
+        },
+        BasicType::Boolean => Value::Boolean(bytes[0] != 0),
+        BasicType::Char => Value::Char(u16::from_be_bytes(be(bytes))),
+        BasicType::Float => Value::Float(f32::from_be_bytes(be(bytes))),
+        BasicType::Double => Value::Double(f64::from_be_bytes(be(bytes))),
+        BasicType::Byte => Value::Byte(bytes[0] as i8),
+        BasicType::Short => Value::Short(i16::from_be_bytes(be(bytes))),
+        BasicType::Int => Value::Int(i32::from_be_bytes(be(bytes))),
+        BasicType::Long => Value::Long(i64::from_be_bytes(be(bytes))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClassLayout, LayoutCache};
+    use crate::hprof_model::ClassId;
+    use std::sync::Arc;
+
+    #[test]
+    fn evicts_layouts_that_were_not_used() {
+        let layout = |id| {
+            Arc::new(ClassLayout {
+                class_object_id: ClassId(id),
+                fields: vec![],
+                size: 0,
+            })
+        };
+        let mut cache = LayoutCache::new(Some(2));
+        cache.insert(layout(1));
+        cache.insert(layout(2));
+        assert!(cache.get(ClassId(1)).is_some());
+        cache.insert(layout(3));
+        assert!(cache.get(ClassId(2)).is_none());
+        assert!(cache.get(ClassId(1)).is_some());
+        assert!(cache.get(ClassId(3)).is_some());
+        // all layouts were used, so the sweep evicts the oldest one
+        cache.insert(layout(4));
+        assert!(cache.get(ClassId(1)).is_none());
+        assert!(cache.get(ClassId(3)).is_some());
+        assert!(cache.get(ClassId(4)).is_some());
+    }
+}
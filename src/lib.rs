@@ -1,37 +1,1321 @@
-#![feature(bufreader_peek)]
-#![feature(bufread_skip_until)]
-#![feature(string_from_utf8_lossy_owned)]
-
-use std::fs::File;
-
+#[cfg(feature = "tokio")]
+pub mod async_reader;
+mod checkpoint;
+pub mod collections;
+pub mod decode;
+pub mod gzip;
+pub mod heap;
+pub mod histogram;
 pub mod hprof_model;
+pub mod instance;
+pub mod layout;
+pub mod names;
+pub mod object_index;
+pub mod progress;
+pub mod push_parser;
 pub mod reader;
+pub mod shallow_size;
+pub mod stack_trace;
+pub mod storage;
+pub mod visitor;
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::reader::HprofReader;
-    use std::fmt::Debug;
-    use std::io::BufReader;
+    use crate::collections::{self, CollectionKind, Entry};
+    use crate::decode;
+    use crate::decode::JavaValue;
+    use crate::gzip::GzipReader;
+    use crate::heap::Heap;
+    use crate::histogram::{sample_histogram, ClassHistogram, ClassKey};
+    use crate::histogram::{SamplingOptions, SamplingUnit};
+    use crate::hprof_model::{
+        BasicType, ClassId, FrameId, ObjectId, ThreadSerial, TraceSerial, U8,
+    };
+    use crate::hprof_model::{
+        HeapDumpTag, PrimitiveArray, RecordKind, RecordTag, SubRecordKind, Value,
+    };
+    use crate::instance::Instance;
+    use crate::names;
+    use crate::progress::{CancellationToken, Cancelled, Progress};
+    use crate::push_parser::{ParseEvent, PushParser};
+    use crate::reader::{AnyHprofReader, FieldDecoding, HprofReader, ReaderOptions};
+    use crate::shallow_size::JvmLayout;
+    use crate::stack_trace::LineNumber;
+    use crate::storage::TableStorage;
+    use crate::visitor::{HprofVisitor, Visit};
+    use std::fs::File;
+    use std::io::{BufReader, Read, Seek, SeekFrom};
+    use std::sync::{Arc, Mutex, OnceLock};
 
     #[test]
     fn it_works() -> std::io::Result<()> {
         let file = File::open("heap.hprof")?;
         let reader = BufReader::new(file);
-        let mut hprof_reader = HprofReader::new(reader)?;
-        let identifier_size = hprof_reader.identifier_size;
+        let hprof_reader = HprofReader::<_, U8>::new(reader)?;
+        let identifier_size = hprof_reader.identifier_size();
         assert_eq!(identifier_size, 8);
         let timestamp = hprof_reader.timestamp;
         println!("{:?}", timestamp);
         let mut c = 0u64;
-        while let Some(res) = hprof_reader.next() {
+        for res in hprof_reader {
             c += 1;
-            match res {
-                Ok(_) => {}
-                Err(data) => return Err(data),
-            }
+            res?;
         }
         println!("{}", c);
         Ok(())
     }
+
+    /// Builds a heap dump with 8-byte identifiers and one record with the given body.
+    fn heap_dump(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut dump = b"JAVA PROFILE 1.0.2\0".to_vec();
+        dump.extend_from_slice(&8u32.to_be_bytes());
+        dump.extend_from_slice(&0u64.to_be_bytes());
+        dump.extend(record(tag, body));
+        dump
+    }
+
+    /// Builds a top-level record with the given body.
+    fn record(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut record = vec![tag];
+        record.extend_from_slice(&0u32.to_be_bytes());
+        record.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        record.extend_from_slice(body);
+        record
+    }
+
+    /// Builds a heap dump with 8-byte identifiers and one heap dump segment.
+    fn heap_dump_segment(sub_records: &[u8]) -> Vec<u8> {
+        heap_dump(0x1C, sub_records)
+    }
+
+    /// Builds a primitive array dump with 8-byte identifiers.
+    fn primitive_array_dump(object_id: u64, type_tag: u8, count: u32, data: &[u8]) -> Vec<u8> {
+        let mut sub_record = vec![0x23];
+        sub_record.extend_from_slice(&object_id.to_be_bytes());
+        sub_record.extend_from_slice(&0u32.to_be_bytes());
+        sub_record.extend_from_slice(&count.to_be_bytes());
+        sub_record.push(type_tag);
+        sub_record.extend_from_slice(data);
+        sub_record
+    }
+
+    /// Builds a class dump with 8-byte identifiers and the given instance fields, as pairs
+    /// of name id and type tag.
+    fn class_dump(class_id: u64, super_class_id: u64, fields: &[(u64, u8)]) -> Vec<u8> {
+        let mut sub_record = vec![0x20];
+        sub_record.extend_from_slice(&class_id.to_be_bytes());
+        sub_record.extend_from_slice(&0u32.to_be_bytes());
+        sub_record.extend_from_slice(&super_class_id.to_be_bytes());
+        sub_record.extend_from_slice(&[0; 5 * 8 + 4 + 2 + 2]);
+        sub_record.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        for (name_id, type_tag) in fields {
+            sub_record.extend_from_slice(&name_id.to_be_bytes());
+            sub_record.push(*type_tag);
+        }
+        sub_record
+    }
+
+    /// Builds an instance dump with 8-byte identifiers.
+    fn instance_dump(object_id: u64, class_id: u64, data: &[u8]) -> Vec<u8> {
+        let mut sub_record = vec![0x21];
+        sub_record.extend_from_slice(&object_id.to_be_bytes());
+        sub_record.extend_from_slice(&0u32.to_be_bytes());
+        sub_record.extend_from_slice(&class_id.to_be_bytes());
+        sub_record.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        sub_record.extend_from_slice(data);
+        sub_record
+    }
+
+    /// Builds an `HPROF_UTF8` record with 8-byte identifiers.
+    fn utf8_record(id: u64, utf8: &str) -> Vec<u8> {
+        record(0x01, &[&id.to_be_bytes()[..], utf8.as_bytes()].concat())
+    }
+
+    #[test]
+    fn decodes_typed_primitive_arrays() -> std::io::Result<()> {
+        let mut sub_records = primitive_array_dump(1, 0x04, 3, &[1, 0, 2]);
+        sub_records.extend(primitive_array_dump(2, 0x05, 2, &[0, b'h', 0x20, 0xAC]));
+        let ints = [1i32, -2, 0x01020304];
+        let int_bytes: Vec<u8> = ints.iter().flat_map(|i| i.to_be_bytes()).collect();
+        sub_records.extend(primitive_array_dump(3, 0x0A, 3, &int_bytes));
+        sub_records.extend(primitive_array_dump(4, 0x0B, 1, &(-5i64).to_be_bytes()));
+        sub_records.extend(primitive_array_dump(5, 0x07, 1, &1.5f64.to_be_bytes()));
+        sub_records.extend(primitive_array_dump(6, 0x08, 2, &[0x7F, 0x80]));
+        sub_records.extend(primitive_array_dump(7, 0x09, 0, &[]));
+        let dump = heap_dump_segment(&sub_records);
+
+        let mut arrays = vec![];
+        for res in HprofReader::<_, U8>::new(std::io::Cursor::new(dump))? {
+            if let RecordTag::HprofHeapDumpSegment { sub_records, .. } = res? {
+                for sub_record in sub_records {
+                    if let HeapDumpTag::HprofGcPrimArrayDump {
+                        element_type,
+                        element_count,
+                        elements: Some(elements),
+                        ..
+                    } = sub_record
+                    {
+                        assert_eq!(elements.element_type(), element_type);
+                        assert_eq!(elements.len(), element_count as usize);
+                        arrays.push(elements);
+                    }
+                }
+            }
+        }
+        assert!(matches!(&arrays[0], PrimitiveArray::Boolean(v) if v == &[true, false, true]));
+        assert!(matches!(&arrays[1], PrimitiveArray::Char(v) if v == &['h' as u16, 0x20AC]));
+        assert!(matches!(&arrays[2], PrimitiveArray::Int(v) if v == &ints));
+        assert!(matches!(&arrays[3], PrimitiveArray::Long(v) if v == &[-5]));
+        assert!(matches!(&arrays[4], PrimitiveArray::Double(v) if v == &[1.5]));
+        assert!(matches!(&arrays[5], PrimitiveArray::Byte(v) if v == &[127, -128]));
+        assert!(matches!(&arrays[6], PrimitiveArray::Short(v) if v.is_empty()));
+        assert!(matches!(arrays[2].get(1), Some(Value::Int(-2))));
+        assert!(arrays[2].get(3).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_input() -> std::io::Result<()> {
+        let mut class_dump = vec![0x20];
+        class_dump.extend_from_slice(&[0; 8 + 4 + 6 * 8 + 4]);
+        class_dump.extend_from_slice(&1u16.to_be_bytes()); // a constant pool entry
+        class_dump.extend_from_slice(&[0, 0, 0x0A, 0, 0, 0, 1, 0, 0, 0, 0]);
+        let mut load_class = vec![0; 4 + 8 + 4 + 8];
+        load_class.extend_from_slice(&[0; 6]); // longer than the record
+                                               // counts that do not fit into the record or segment
+        let trace = [[0; 8].as_slice(), &u32::MAX.to_be_bytes()].concat();
+        let mut object_array = vec![0x22];
+        object_array.extend_from_slice(&[0; 8 + 4]);
+        object_array.extend_from_slice(&u32::MAX.to_be_bytes());
+        object_array.extend_from_slice(&[0; 8]);
+        let dumps = [
+            heap_dump(0x7F, &[]),
+            heap_dump(0x2C, &[0]),
+            heap_dump(0x01, &[0; 4]),
+            heap_dump(0x02, &load_class),
+            heap_dump_segment(&class_dump),
+            heap_dump_segment(&[0x42, 0, 0]),
+            heap_dump(0x05, &trace),
+            heap_dump_segment(&object_array),
+            heap_dump_segment(&primitive_array_dump(1, 0x0B, u32::MAX, &[])),
+        ];
+        for dump in dumps {
+            let error = PushParser::new().feed(&dump).err().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+            // feeding byte by byte finds the same error
+            let mut parser = PushParser::new();
+            let error = dump.chunks(1).find_map(|b| parser.feed(b).err()).unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+            let reader = HprofReader::<_, U8>::new(std::io::Cursor::new(dump))?;
+            assert!(reader.into_iter().any(|res| res.is_err()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_all_gc_roots() -> std::io::Result<()> {
+        let mut sub_records = vec![0xFF];
+        sub_records.extend_from_slice(&1u64.to_be_bytes());
+        for (tag, object_id, thread_serial_number) in [(0x04, 2u64, 3u32), (0x06, 4, 5)] {
+            sub_records.push(tag);
+            sub_records.extend_from_slice(&object_id.to_be_bytes());
+            sub_records.extend_from_slice(&thread_serial_number.to_be_bytes());
+        }
+        sub_records.push(0x07);
+        sub_records.extend_from_slice(&6u64.to_be_bytes());
+        let dump = heap_dump_segment(&sub_records);
+
+        let check = |roots: Vec<HeapDumpTag>| {
+            assert!(matches!(
+                roots[..],
+                [
+                    HeapDumpTag::HprofGcRootUnknown {
+                        object_id: ObjectId(1)
+                    },
+                    HeapDumpTag::HprofGcRootNativeStack {
+                        object_id: ObjectId(2),
+                        thread_serial_number: ThreadSerial(3)
+                    },
+                    HeapDumpTag::HprofGcRootThreadBlock {
+                        object_id: ObjectId(4),
+                        thread_serial_number: ThreadSerial(5)
+                    },
+                    HeapDumpTag::HprofGcRootMonitorUsed {
+                        object_id: ObjectId(6)
+                    },
+                ]
+            ));
+        };
+        let mut roots = vec![];
+        for res in HprofReader::<_, U8>::new(std::io::Cursor::new(dump.clone()))? {
+            if let RecordTag::HprofHeapDumpSegment { sub_records, .. } = res? {
+                roots.extend(sub_records);
+            }
+        }
+        check(roots);
+        let events = PushParser::new().feed(&dump)?.events;
+        check(
+            events
+                .into_iter()
+                .filter_map(|event| match event {
+                    ParseEvent::SubRecord(sub_record) => Some(sub_record),
+                    ParseEvent::Record(_) => None,
+                })
+                .collect(),
+        );
+
+        // the rest of a segment is skipped after a malformed sub-record
+        let mut dump = heap_dump_segment(&[0x42, 0, 0]);
+        dump.extend(utf8_record(1, "next"));
+        let mut hprof_reader = HprofReader::<_, U8>::new(std::io::Cursor::new(dump))?;
+        assert!(hprof_reader.next().unwrap().is_err());
+        let next = hprof_reader.next().unwrap()?;
+        assert!(matches!(next, RecordTag::HprofUtf8 { utf8, .. } if &*utf8 == "next"));
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_fields_with_missing_names() -> std::io::Result<()> {
+        let mut sub_records = class_dump(1, 0, &[(98, 0x0A)]);
+        // the name of the field of class 2 has no HPROF_UTF8 record
+        sub_records.extend(class_dump(2, 1, &[(99, 0x0B)]));
+        let data = [&(-1i64).to_be_bytes()[..], &7i32.to_be_bytes()].concat();
+        sub_records.extend(instance_dump(3, 2, &data));
+        let mut dump = heap_dump(0x01, &[&98u64.to_be_bytes()[..], b"count"].concat());
+        dump.extend(record(0x1C, &sub_records));
+
+        let mut hprof_reader = HprofReader::<_, U8>::new(std::io::Cursor::new(dump))?;
+        let mut instances = vec![];
+        for res in hprof_reader.by_ref() {
+            if let RecordTag::HprofHeapDumpSegment { sub_records, .. } = res? {
+                instances.extend(sub_records.into_iter().filter_map(|s| match s {
+                    HeapDumpTag::HprofGcInstanceDump {
+                        instance_field_values,
+                        ..
+                    } => Some(instance_field_values),
+                    _ => None,
+                }));
+            }
+        }
+        assert!(matches!(instances[0][..], [Value::Long(-1), Value::Int(7)]));
+        let layout = hprof_reader.class_layout(ClassId(2))?;
+        assert!(layout.fields[0].name.is_none());
+        assert_eq!(layout.fields[1].name.as_deref(), Some("count"));
+        assert_eq!(layout.fields[1].offset, 8);
+        // the names of inherited fields are shared with the layout of the superclass
+        let super_layout = hprof_reader.class_layout(ClassId(1))?;
+        let name = super_layout.fields[0].name.as_ref().unwrap();
+        assert!(Arc::ptr_eq(layout.fields[1].name.as_ref().unwrap(), name));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_cyclic_superclass_chains() -> std::io::Result<()> {
+        // classes 1 and 2 are each other's superclass
+        let mut sub_records = class_dump(1, 2, &[(98, 0x0A)]);
+        sub_records.extend(class_dump(2, 1, &[(99, 0x0A)]));
+        sub_records.extend(instance_dump(3, 1, &[0; 8]));
+        let dump = heap_dump_segment(&sub_records);
+
+        let mut hprof_reader = HprofReader::<_, U8>::new(std::io::Cursor::new(dump.clone()))?;
+        let error = hprof_reader.find_map(Result::err).unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        let options = ReaderOptions {
+            instance_fields: FieldDecoding::None,
+            ..ReaderOptions::default()
+        };
+        let cursor = std::io::Cursor::new(dump);
+        let mut hprof_reader = HprofReader::<_, U8>::with_options(cursor, options)?;
+        let mut histogram = ClassHistogram::new(8);
+        hprof_reader.accept(&mut histogram)?;
+        let error = histogram.entries(&hprof_reader).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unsupported_identifier_size() {
+        let header = |identifier_size: u32| {
+            let mut header = b"JAVA PROFILE 1.0.2\0".to_vec();
+            header.extend_from_slice(&identifier_size.to_be_bytes());
+            header.extend_from_slice(&0u64.to_be_bytes());
+            std::io::Cursor::new(header)
+        };
+        let error = AnyHprofReader::new(header(2)).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let reader = AnyHprofReader::new(header(4)).unwrap();
+        assert!(matches!(reader, AnyHprofReader::U4(_)));
+        assert_eq!(reader.identifier_size(), 4);
+        let error = HprofReader::<_, U8>::new(header(4)).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn skips_with_options() -> std::io::Result<()> {
+        let file = File::open("heap.hprof")?;
+        let options = ReaderOptions {
+            skip_primitive_array_bodies: true,
+            instance_fields: FieldDecoding::ReferencesOnly,
+            skipped_sub_records: [SubRecordKind::HprofGcRootStickyClass].into(),
+            ..Default::default()
+        };
+        let hprof_reader = HprofReader::<_, U8>::with_options(BufReader::new(file), options)?;
+        for res in hprof_reader {
+            if let RecordTag::HprofHeapDumpSegment { sub_records, .. } = res? {
+                for sub_record in sub_records {
+                    match sub_record {
+                        HeapDumpTag::HprofGcRootStickyClass { .. } => panic!("not skipped"),
+                        HeapDumpTag::HprofGcInstanceDump {
+                            instance_field_values,
+                            ..
+                        } => assert!(instance_field_values
+                            .iter()
+                            .all(|v| matches!(v, Value::Object { .. }))),
+                        HeapDumpTag::HprofGcPrimArrayDump { elements, .. } => {
+                            assert!(elements.is_none())
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn instance_values_follow_class_layout() -> std::io::Result<()> {
+        let file = File::open("heap.hprof")?;
+        let mut hprof_reader = HprofReader::<_, U8>::new(BufReader::new(file))?;
+        let mut instances = vec![];
+        let mut classes = std::collections::HashMap::new();
+        for res in hprof_reader.by_ref() {
+            if let RecordTag::HprofHeapDumpSegment { sub_records, .. } = res? {
+                for sub_record in sub_records {
+                    match sub_record {
+                        HeapDumpTag::HprofGcInstanceDump {
+                            object_id,
+                            class_object_id,
+                            instance_field_values,
+                            ..
+                        } => instances.push((object_id, class_object_id, instance_field_values)),
+                        HeapDumpTag::HprofGcClassDump(class) => {
+                            classes.insert(class.class_object_id, class);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        assert!(!instances.is_empty());
+        let mut threads = 0;
+        for (object_id, class_object_id, values) in instances {
+            let layout = hprof_reader.class_layout(class_object_id)?;
+            assert_eq!(layout.fields.len(), values.len());
+            // the fields of the class come first, followed by those of its superclasses
+            let mut fields = layout.fields.iter();
+            let mut offset = 0;
+            let mut class_id = class_object_id;
+            while !class_id.is_null() {
+                let class = &classes[&class_id];
+                for field in &class.instance_fields {
+                    let layout_field = fields.next().unwrap();
+                    assert_eq!(layout_field.declaring_class_id, class_id);
+                    assert_eq!(layout_field.name_id, field.name_id);
+                    let name = hprof_reader.name(field.name_id)?.unwrap();
+                    assert_eq!(layout_field.name.as_deref(), Some(&*name));
+                    assert_eq!(layout_field.field_type, field.field_type);
+                    assert_eq!(layout_field.offset, offset);
+                    offset += field.field_type.size(8);
+                }
+                class_id = class.super_class_object_id;
+            }
+            assert!(fields.next().is_none());
+            assert_eq!(layout.size, offset);
+            let instance = hprof_reader.resolve_instance(object_id, class_object_id, values)?;
+            for (i, (class_id, name, value)) in instance.fields().enumerate() {
+                let name = name.unwrap();
+                let expected = format!("{value:?}");
+                let declared = instance.get_declared(class_id, name);
+                assert_eq!(format!("{:?}", declared.unwrap()), expected);
+                if layout.field_index_by_name(name) == Some(i) {
+                    assert_eq!(format!("{:?}", instance.get(name).unwrap()), expected);
+                }
+            }
+            let class_name = hprof_reader.class_name(class_object_id)?.unwrap();
+            if class_name == "java/lang/Thread" {
+                assert!(instance.get_long("tid").is_some());
+                assert!(instance.get_ref("name").is_some());
+                assert!(instance.get_int("name").is_none());
+                threads += 1;
+            }
+        }
+        assert!(threads > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn formats_class_names() {
+        let cases = [
+            (
+                "java/lang/String",
+                "java.lang.String",
+                "java.lang.String",
+                "String",
+            ),
+            (
+                "java/util/Map$Entry",
+                "java.util.Map$Entry",
+                "java.util.Map.Entry",
+                "Entry",
+            ),
+            ("[[I", "[[I", "int[][]", "int[][]"),
+            (
+                "[Ljava/util/Map$Entry;",
+                "[Ljava.util.Map$Entry;",
+                "java.util.Map.Entry[]",
+                "Entry[]",
+            ),
+            (
+                "com/example/Foo$1",
+                "com.example.Foo$1",
+                "com.example.Foo$1",
+                "Foo$1",
+            ),
+            (
+                "com/example/Foo$$Lambda$14/0x0000000800c03000",
+                "com.example.Foo$$Lambda$14/0x0000000800c03000",
+                "com.example.Foo$$Lambda$14",
+                "Foo$$Lambda$14",
+            ),
+            (
+                "com/example/Foo$$Lambda+0x000001f0010030f8",
+                "com.example.Foo$$Lambda/0x000001f0010030f8",
+                "com.example.Foo$$Lambda",
+                "Foo$$Lambda",
+            ),
+        ];
+        for (name, binary, source, simple) in cases {
+            assert_eq!(names::binary_name(name), binary);
+            assert_eq!(names::source_name(name), source);
+            assert_eq!(names::simple_name(name), simple);
+            assert_eq!(names::is_hidden(name), name.contains("Lambda"));
+        }
+    }
+
+    #[test]
+    fn resolves_stack_traces() -> std::io::Result<()> {
+        let file = File::open("heap.hprof")?;
+        let mut hprof_reader = HprofReader::<_, U8>::new(BufReader::new(file))?;
+        let mut traces = vec![];
+        for res in hprof_reader.by_ref() {
+            if let RecordTag::HprofTrace {
+                stack_trace_serial_number,
+                stack_frame_ids,
+                ..
+            } = res?
+            {
+                traces.push((stack_trace_serial_number, stack_frame_ids.len()));
+            }
+        }
+        assert!(traces.iter().any(|&(_, frames)| frames > 0));
+        for (serial_number, frames) in traces {
+            let trace = hprof_reader.stack_trace(serial_number)?.unwrap();
+            assert_eq!(trace.frames.len(), frames);
+            for (line, frame) in trace.to_string().lines().zip(&trace.frames) {
+                assert!(line.starts_with("\tat "));
+                assert!(frame.method_name.is_some());
+            }
+        }
+        assert_eq!(LineNumber::from(-3), LineNumber::Native);
+        assert_eq!(LineNumber::from(12), LineNumber::Line(12));
+
+        // a trace whose frame has no HPROF_FRAME record
+        let mut trace = vec![];
+        for n in [1u32, 2, 1] {
+            trace.extend_from_slice(&n.to_be_bytes());
+        }
+        trace.extend_from_slice(&42u64.to_be_bytes());
+        let mut hprof_reader =
+            HprofReader::<_, U8>::new(std::io::Cursor::new(heap_dump(0x05, &trace)))?;
+        for res in hprof_reader.by_ref() {
+            res?;
+        }
+        let traces = hprof_reader.stack_traces()?;
+        assert_eq!(traces.len(), 1);
+        let frame = &traces[0].frames[0];
+        assert_eq!(frame.frame_id, FrameId(42));
+        assert!(frame.class_name.is_none() && frame.method_name.is_none());
+        assert_eq!(frame.line_number, LineNumber::Unknown);
+        Ok(())
+    }
+
+    #[test]
+    fn object_index_finds_all_objects() -> std::io::Result<()> {
+        let options = ReaderOptions {
+            index_objects: true,
+            ..Default::default()
+        };
+        let file = BufReader::new(File::open("heap.hprof")?);
+        let mut hprof_reader = HprofReader::<_, U8>::with_options(file, options)?;
+        let mut expected = vec![];
+        for res in hprof_reader.by_ref() {
+            if let RecordTag::HprofHeapDumpSegment { sub_records, .. } = res? {
+                expected.extend(sub_records.iter().filter_map(|s| match s {
+                    HeapDumpTag::HprofGcClassDump(class) => Some(class.class_object_id.into()),
+                    HeapDumpTag::HprofGcInstanceDump { object_id, .. } => Some(*object_id),
+                    HeapDumpTag::HprofGcObjArrayDump {
+                        array_object_id, ..
+                    }
+                    | HeapDumpTag::HprofGcPrimArrayDump {
+                        array_object_id, ..
+                    } => Some(*array_object_id),
+                    _ => None,
+                }));
+            }
+        }
+        // the index was built while iterating
+        let index = hprof_reader.index_objects()?;
+        assert_eq!(index.len(), expected.len());
+        assert!(index.ids().is_sorted());
+        for object_id in expected.into_iter().step_by(1000) {
+            let i = index.index_of(object_id).unwrap();
+            assert_eq!(index.id(i), object_id);
+            match hprof_reader.read_object_at(index.offset(i))? {
+                HeapDumpTag::HprofGcClassDump(class) => {
+                    assert_eq!(ObjectId::from(class.class_object_id), object_id)
+                }
+                HeapDumpTag::HprofGcInstanceDump { object_id: id, .. } => assert_eq!(id, object_id),
+                HeapDumpTag::HprofGcObjArrayDump {
+                    array_object_id, ..
+                }
+                | HeapDumpTag::HprofGcPrimArrayDump {
+                    array_object_id, ..
+                } => assert_eq!(array_object_id, object_id),
+                _ => panic!("not an object"),
+            }
+        }
+        for offset in [0, index.offset(0) + 1] {
+            let error = hprof_reader.read_object_at(offset).err().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        // the reader is still at the end of the dump
+        assert!(hprof_reader.next().is_none());
+
+        let mut hprof_reader =
+            HprofReader::<_, U8>::new(BufReader::new(File::open("heap.hprof")?))?;
+        let error = hprof_reader.index_objects().err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    type TestHeap = Heap<BufReader<File>, U8>;
+
+    /// Skips all sub-records before they are decoded.
+    struct SkipAll;
+
+    impl HprofVisitor for SkipAll {
+        fn visit_sub_record_header(&mut self, _: SubRecordKind, _: ObjectId) -> Visit {
+            Visit::Skip
+        }
+    }
+
+    /// Opens `heap.hprof` as a [`Heap`]. Only the first call reads and indexes the dump, the
+    /// others resume from a checkpoint that includes the index.
+    fn test_heap() -> std::io::Result<TestHeap> {
+        static CHECKPOINT: OnceLock<Vec<u8>> = OnceLock::new();
+        let options = ReaderOptions {
+            index_objects: true,
+            ..Default::default()
+        };
+        let file = BufReader::new(File::open("heap.hprof")?);
+        if let Some(checkpoint) = CHECKPOINT.get() {
+            return Heap::new(HprofReader::resume(
+                file,
+                options,
+                &mut checkpoint.as_slice(),
+            )?);
+        }
+        let mut hprof_reader = HprofReader::with_options(file, options)?;
+        hprof_reader.accept(&mut SkipAll)?;
+        let mut checkpoint = vec![];
+        hprof_reader.write_checkpoint(&mut checkpoint)?;
+        let _ = CHECKPOINT.set(checkpoint);
+        Heap::new(hprof_reader)
+    }
+
+    /// Calls `f` with the id of each object of `heap.hprof`, in the order of the index.
+    fn for_each_object(
+        mut f: impl FnMut(&mut TestHeap, ObjectId) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        let mut heap = test_heap()?;
+        let ids = heap.index().ids().to_vec();
+        for object_id in ids {
+            f(&mut heap, object_id)?;
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with each instance of `heap.hprof`.
+    fn for_each_instance(
+        mut f: impl FnMut(&mut TestHeap, Instance) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        for_each_object(|heap, object_id| match heap.instance(object_id)? {
+            Some(instance) => f(heap, instance),
+            None => Ok(()),
+        })
+    }
+
+    #[test]
+    fn decodes_strings() -> std::io::Result<()> {
+        let mut strings = vec![];
+        for_each_instance(|heap, instance| {
+            let class_name = heap.reader().class_name(instance.class_object_id())?;
+            if class_name.as_deref() == Some("java/lang/String") {
+                strings.push(decode::string(heap, &instance)?);
+            } else {
+                let error = decode::string(heap, &instance).unwrap_err();
+                assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+            }
+            Ok(())
+        })?;
+        assert!(strings.iter().any(|s| s == "main"));
+        assert!(strings.iter().any(|s| s == "java.lang.String"));
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_jdk_values() -> std::io::Result<()> {
+        let mut values = vec![];
+        for_each_object(|heap, object_id| {
+            values.extend(decode::value_of(heap, object_id)?);
+            Ok(())
+        })?;
+        assert!(values.iter().any(|v| matches!(v, JavaValue::Int(_))));
+        assert!(values.contains(&JavaValue::String("main".to_string())));
+        assert!(values.iter().any(|v| matches!(
+            v,
+            JavaValue::Enum { ordinal: 0, name } if name == "NANOSECONDS"
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn walks_collections() -> std::io::Result<()> {
+        let mut kinds = std::collections::HashSet::new();
+        let mut string_keys = 0;
+        for_each_instance(|heap, instance| {
+            let Some(collection) = collections::collection(heap, &instance)? else {
+                return Ok(());
+            };
+            kinds.insert(collection.kind);
+            let size = match collection.kind {
+                CollectionKind::HashSet | CollectionKind::LinkedHashSet => {
+                    let map = heap.instance(instance.get_ref("map").unwrap())?.unwrap();
+                    map.get_int("size")
+                }
+                _ => instance.get_int("size"),
+            };
+            if let Some(size) = size {
+                assert_eq!(collection.stats.size, size as usize);
+            }
+            if collection.kind.is_map() {
+                assert_eq!(collection.entries().count(), collection.stats.size);
+            } else {
+                assert!(collection.values.is_empty());
+            }
+            if collection.kind == CollectionKind::HashMap {
+                assert!(collection.stats.used_bins <= collection.stats.capacity.unwrap());
+                for key in collection.elements.iter().take(1) {
+                    if let Some(JavaValue::String(_)) = decode::value_of(heap, *key)? {
+                        string_keys += 1;
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        assert!(kinds.contains(&CollectionKind::ArrayList));
+        assert!(kinds.contains(&CollectionKind::HashMap));
+        assert!(kinds.contains(&CollectionKind::ConcurrentHashMap));
+        assert!(string_keys > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_linked_hash_map_entries_off_the_linked_list() -> std::io::Result<()> {
+        let names = ["java/util/LinkedHashMap", "head", "table", "size"];
+        let node_names = ["key", "value", "next", "after"];
+        let mut dump = heap_dump(
+            0x01,
+            &[&50u64.to_be_bytes()[..], names[0].as_bytes()].concat(),
+        );
+        for (id, name) in (51..).zip(&names[1..]).chain((54..).zip(&node_names)) {
+            dump.extend(utf8_record(id, name));
+        }
+        let mut load_class = 1u32.to_be_bytes().to_vec();
+        load_class.extend_from_slice(&100u64.to_be_bytes());
+        load_class.extend_from_slice(&0u32.to_be_bytes());
+        load_class.extend_from_slice(&50u64.to_be_bytes());
+        dump.extend(record(0x02, &load_class));
+
+        let mut sub_records = class_dump(100, 0, &[(51, 0x02), (52, 0x02), (53, 0x0A)]);
+        sub_records.extend(class_dump(
+            101,
+            0,
+            &[(54, 0x02), (55, 0x02), (56, 0x02), (57, 0x02)],
+        ));
+        let map = [
+            &10u64.to_be_bytes()[..],
+            &20u64.to_be_bytes(),
+            &2u32.to_be_bytes(),
+        ];
+        sub_records.extend(instance_dump(1, 100, &map.concat()));
+        // the table, an object array with both nodes
+        sub_records.push(0x22);
+        sub_records.extend_from_slice(&20u64.to_be_bytes());
+        sub_records.extend_from_slice(&0u32.to_be_bytes());
+        sub_records.extend_from_slice(&2u32.to_be_bytes());
+        for id in [102u64, 10, 11] {
+            sub_records.extend_from_slice(&id.to_be_bytes());
+        }
+        // the after link of the head is broken, so node 11 is not on the linked list
+        for (node_id, key, value) in [(10u64, 30u64, 31u64), (11, 32, 33)] {
+            let node: Vec<u8> = [key, value, 0, 0]
+                .iter()
+                .flat_map(|i| i.to_be_bytes())
+                .collect();
+            sub_records.extend(instance_dump(node_id, 101, &node));
+        }
+        dump.extend(record(0x1C, &sub_records));
+
+        let options = ReaderOptions {
+            index_objects: true,
+            ..Default::default()
+        };
+        let reader = HprofReader::<_, U8>::with_options(std::io::Cursor::new(dump), options)?;
+        let mut heap = Heap::new(reader)?;
+        let collection = collections::collection_of(&mut heap, ObjectId(1))?.unwrap();
+        assert_eq!(collection.kind, CollectionKind::LinkedHashMap);
+        assert_eq!(collection.elements, [ObjectId(30), ObjectId(32)]);
+        assert_eq!(collection.values, [ObjectId(31), ObjectId(33)]);
+
+        // the walk stops reading nodes where the caller stops
+        let instance = heap.instance(ObjectId(1))?.unwrap();
+        let mut walk = collections::walk(&mut heap, &instance)?.unwrap();
+        let entry = Entry {
+            element: ObjectId(30),
+            value: Some(ObjectId(31)),
+        };
+        assert_eq!(walk.next().transpose()?, Some(entry));
+        assert_eq!(walk.stats().size, 1);
+        Ok(())
+    }
+
+    /// Counts instances and stops the reader at the `stop_at`-th one.
+    struct InstanceCounter {
+        count: u64,
+        stop_at: u64,
+    }
+
+    impl HprofVisitor for InstanceCounter {
+        fn visit_instance(
+            &mut self,
+            _: ObjectId,
+            _: TraceSerial,
+            _: ClassId,
+            _: &[Value],
+        ) -> Visit {
+            self.count += 1;
+            if self.count == self.stop_at {
+                Visit::Stop
+            } else {
+                Visit::Continue
+            }
+        }
+    }
+
+    fn count_instances() -> std::io::Result<u64> {
+        let mut count = 0;
+        for res in HprofReader::<_, U8>::new(BufReader::new(File::open("heap.hprof")?))? {
+            if let RecordTag::HprofHeapDumpSegment { sub_records, .. } = res? {
+                count += sub_records
+                    .iter()
+                    .filter(|s| matches!(s, HeapDumpTag::HprofGcInstanceDump { .. }))
+                    .count() as u64;
+            }
+        }
+        Ok(count)
+    }
+
+    #[test]
+    fn visitor_sees_all_instances() -> std::io::Result<()> {
+        let expected = count_instances()?;
+        let mut hprof_reader =
+            HprofReader::<_, U8>::new(BufReader::new(File::open("heap.hprof")?))?;
+        let mut counter = InstanceCounter {
+            count: 0,
+            stop_at: 100,
+        };
+        hprof_reader.accept(&mut counter)?;
+        assert_eq!(counter.count, 100);
+        // continue in the middle of the segment
+        hprof_reader.accept(&mut counter)?;
+        assert_eq!(counter.count, expected);
+        Ok(())
+    }
+
+    /// Skips every second instance and primitive array before it is decoded, and formats
+    /// the others.
+    #[derive(Default)]
+    struct SkippingVisitor {
+        seen: u64,
+        visited: Vec<String>,
+    }
+
+    impl HprofVisitor for SkippingVisitor {
+        fn visit_sub_record_header(&mut self, kind: SubRecordKind, _: ObjectId) -> Visit {
+            if !matches!(
+                kind,
+                SubRecordKind::HprofGcInstanceDump | SubRecordKind::HprofGcPrimArrayDump
+            ) {
+                return Visit::Continue;
+            }
+            self.seen += 1;
+            if self.seen.is_multiple_of(2) {
+                Visit::Skip
+            } else {
+                Visit::Continue
+            }
+        }
+
+        fn visit_instance(
+            &mut self,
+            object_id: ObjectId,
+            _: TraceSerial,
+            _: ClassId,
+            instance_field_values: &[Value],
+        ) -> Visit {
+            let values = format!("{instance_field_values:?}");
+            self.visited.push(format!("{object_id:?} {values}"));
+            Visit::Continue
+        }
+
+        fn visit_primitive_array(
+            &mut self,
+            array_object_id: ObjectId,
+            _: TraceSerial,
+            _: BasicType,
+            _: u32,
+            elements: Option<&PrimitiveArray>,
+        ) -> Visit {
+            let elements = elements.unwrap();
+            let values: Vec<_> = (0..elements.len()).map(|i| elements.get(i)).collect();
+            self.visited.push(format!("{array_object_id:?} {values:?}"));
+            Visit::Continue
+        }
+    }
+
+    #[test]
+    fn visitor_skips_sub_records_before_decoding() -> std::io::Result<()> {
+        let mut expected = vec![];
+        for res in HprofReader::<_, U8>::new(BufReader::new(File::open("heap.hprof")?))? {
+            let RecordTag::HprofHeapDumpSegment { sub_records, .. } = res? else {
+                continue;
+            };
+            for sub_record in sub_records {
+                match sub_record {
+                    HeapDumpTag::HprofGcInstanceDump {
+                        object_id,
+                        instance_field_values,
+                        ..
+                    } => expected.push(format!("{object_id:?} {instance_field_values:?}")),
+                    HeapDumpTag::HprofGcPrimArrayDump {
+                        array_object_id,
+                        elements: Some(elements),
+                        ..
+                    } => {
+                        let values: Vec<_> = (0..elements.len()).map(|i| elements.get(i)).collect();
+                        expected.push(format!("{array_object_id:?} {values:?}"));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let expected: Vec<_> = expected.into_iter().step_by(2).collect();
+
+        let mut hprof_reader =
+            HprofReader::<_, U8>::new(BufReader::new(File::open("heap.hprof")?))?;
+        let mut visitor = SkippingVisitor::default();
+        hprof_reader.accept(&mut visitor)?;
+        assert!(expected.len() > 100);
+        assert_eq!(visitor.visited, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn resumes_from_checkpoint() -> std::io::Result<()> {
+        let expected = count_instances()?;
+        let mut hprof_reader =
+            HprofReader::<_, U8>::new(BufReader::new(File::open("heap.hprof")?))?;
+        let mut counter = InstanceCounter {
+            count: 0,
+            stop_at: 100,
+        };
+        hprof_reader.accept(&mut counter)?;
+        let mut checkpoint = vec![];
+        hprof_reader.write_checkpoint(&mut checkpoint)?;
+        hprof_reader.accept(&mut counter)?;
+
+        let options = ReaderOptions {
+            class_storage: TableStorage::SortedVec,
+            ..Default::default()
+        };
+        let file = BufReader::new(File::open("heap.hprof")?);
+        let mut resumed = HprofReader::<_, U8>::resume(file, options, &mut checkpoint.as_slice())?;
+        assert_eq!(
+            resumed.stack_traces()?.len(),
+            hprof_reader.stack_traces()?.len()
+        );
+        counter.count = 100;
+        resumed.accept(&mut counter)?;
+        assert_eq!(counter.count, expected);
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn async_reader_yields_the_records_of_the_sync_reader() -> std::io::Result<()> {
+        use crate::async_reader::AsyncHprofReader;
+
+        // the kinds of the records, with the kinds of the sub-records of each segment
+        let mut expected = vec![];
+        for res in HprofReader::<_, U8>::new(BufReader::new(File::open("heap.hprof")?))? {
+            let record = res?;
+            let sub_records = match &record {
+                RecordTag::HprofHeapDumpSegment { sub_records, .. } => {
+                    sub_records.iter().map(SubRecordKind::from).collect()
+                }
+                _ => vec![],
+            };
+            expected.push((RecordKind::from(&record), sub_records));
+        }
+        let file = tokio::fs::File::open("heap.hprof").await?;
+        let mut async_reader = AsyncHprofReader::new(tokio::io::BufReader::new(file)).await?;
+        assert_eq!(async_reader.identifier_size, 8);
+        let mut records: Vec<(RecordKind, Vec<SubRecordKind>)> = vec![];
+        while let Some(res) = async_reader.next_event().await {
+            match res? {
+                ParseEvent::Record(record) => {
+                    if let RecordTag::HprofHeapDumpSegment { sub_records, .. } = &record {
+                        assert!(sub_records.is_empty());
+                    }
+                    records.push((RecordKind::from(&record), vec![]));
+                }
+                ParseEvent::SubRecord(sub_record) => {
+                    let (kind, sub_records) = records.last_mut().unwrap();
+                    assert_eq!(*kind, RecordKind::HprofHeapDumpSegment);
+                    sub_records.push(SubRecordKind::from(&sub_record));
+                }
+            }
+        }
+        assert!(expected
+            .iter()
+            .any(|(_, sub_records)| !sub_records.is_empty()));
+        assert_eq!(records, expected);
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn records_round_trip_through_serde() -> std::io::Result<()> {
+        let mut tags = std::collections::HashSet::new();
+        for res in HprofReader::<_, U8>::new(BufReader::new(File::open("heap.hprof")?))? {
+            let record = res?;
+            let json = serde_json::to_value(&record)?;
+            tags.insert(json["tag"].as_str().unwrap().to_string());
+            let bytes = rmp_serde::to_vec_named(&record).unwrap();
+            let decoded: RecordTag = rmp_serde::from_slice(&bytes).unwrap();
+            assert_eq!(rmp_serde::to_vec_named(&decoded).unwrap(), bytes);
+        }
+        assert!(tags.contains("HPROF_UTF8"));
+        assert!(tags.contains("HPROF_HEAP_DUMP_SEGMENT"));
+
+        let value = serde_json::to_value(Value::Object {
+            object_id: ObjectId(0x1000),
+        })?;
+        assert_eq!(value["type"], "object");
+        assert_eq!(value["value"]["object_id"], 0x1000);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_gzip_compressed_dump() -> std::io::Result<()> {
+        let file = File::open("heap.hprof.gz")?;
+        let mut c = 0;
+        for res in HprofReader::<_, U8>::new(BufReader::new(file))? {
+            c += 1;
+            res?;
+        }
+        assert!(c > 0);
+        let mut compressed = vec![];
+        File::open("heap.hprof.gz")?.read_to_end(&mut compressed)?;
+        let error = PushParser::new().feed(&compressed[..100]).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        // random access across member boundaries
+        let mut plain = vec![];
+        GzipReader::new(File::open("heap.hprof.gz")?)?.read_to_end(&mut plain)?;
+        let mut gzip = GzipReader::new(File::open("heap.hprof.gz")?)?;
+        for offset in [2_000_000, 100, 1_048_570, 2_900_000, 0] {
+            let mut buf = [0; 64];
+            gzip.seek(SeekFrom::Start(offset))?;
+            gzip.read_exact(&mut buf)?;
+            assert_eq!(buf, plain[offset as usize..offset as usize + 64]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reports_progress_and_cancels() -> std::io::Result<()> {
+        let file = File::open("heap.hprof")?;
+        let total = file.metadata()?.len();
+        let mut hprof_reader = HprofReader::<_, U8>::new(BufReader::new(file))?;
+        let token = CancellationToken::new();
+        let progress = Arc::new(Mutex::new(vec![]));
+        let callback_progress = progress.clone();
+        let callback_token = token.clone();
+        hprof_reader.set_progress_callback(move |p: Progress| {
+            assert_eq!(p.total_bytes, Some(total));
+            if p.record_kind == Some(RecordKind::HprofHeapDumpSegment) {
+                callback_token.cancel();
+            }
+            callback_progress.lock().unwrap().push(p.bytes_consumed);
+        })?;
+        hprof_reader.set_cancellation_token(token);
+        let error = hprof_reader
+            .find_map(Result::err)
+            .expect("reader was not cancelled");
+        assert!(Cancelled::is_cancelled(&error));
+        assert!(progress.lock().unwrap().is_sorted());
+
+        // records are only reported when their kind changes or after many bytes
+        let file = File::open("heap.hprof")?;
+        let mut hprof_reader = HprofReader::<_, U8>::new(BufReader::new(file))?;
+        let kinds = Arc::new(Mutex::new(vec![]));
+        let callback_kinds = kinds.clone();
+        hprof_reader.set_progress_callback(move |p: Progress| {
+            callback_kinds.lock().unwrap().push(p.record_kind);
+        })?;
+        let records = hprof_reader
+            .by_ref()
+            .try_fold(0, |n, res| res.map(|_| n + 1))?;
+        let kinds = kinds.lock().unwrap();
+        assert!(kinds.len() < records / 100);
+        assert!(kinds.contains(&Some(RecordKind::HprofHeapDumpSegment)));
+        Ok(())
+    }
+
+    #[test]
+    fn cancels_at_unknown_records() -> std::io::Result<()> {
+        let dump = heap_dump(0x7F, &[]);
+        let mut hprof_reader = HprofReader::<_, U8>::new(std::io::Cursor::new(dump))?;
+        let kinds = Arc::new(Mutex::new(vec![]));
+        let callback_kinds = kinds.clone();
+        hprof_reader.set_progress_callback(move |p: Progress| {
+            callback_kinds.lock().unwrap().push(p.record_kind);
+        })?;
+        let token = CancellationToken::new();
+        token.cancel();
+        hprof_reader.set_cancellation_token(token);
+        let error = hprof_reader.next().unwrap().err().unwrap();
+        assert!(Cancelled::is_cancelled(&error));
+
+        let dump = heap_dump(0x7F, &[]);
+        let mut hprof_reader = HprofReader::<_, U8>::new(std::io::Cursor::new(dump))?;
+        let callback_kinds = kinds.clone();
+        hprof_reader.set_progress_callback(move |p: Progress| {
+            callback_kinds.lock().unwrap().push(p.record_kind);
+        })?;
+        assert!(hprof_reader.next().unwrap().is_err()); // the tag is not supported
+        assert_eq!(*kinds.lock().unwrap(), [None]);
+        Ok(())
+    }
+
+    #[test]
+    fn push_parser_matches_reader() -> std::io::Result<()> {
+        let mut expected = (0, 0);
+        for res in HprofReader::<_, U8>::new(BufReader::new(File::open("heap.hprof")?))? {
+            expected.0 += 1;
+            if let RecordTag::HprofHeapDumpSegment { sub_records, .. } = res? {
+                expected.1 += sub_records.len();
+            }
+        }
+        let bytes = std::fs::read("heap.hprof")?;
+        let mut parser = PushParser::new();
+        let mut actual = (0, 0);
+        for chunk in bytes.chunks(1000) {
+            let parsed = parser.feed(chunk)?;
+            assert!(parsed.bytes_needed > 0);
+            for event in parsed.events {
+                match event {
+                    ParseEvent::Record(_) => actual.0 += 1,
+                    ParseEvent::SubRecord(_) => actual.1 += 1,
+                }
+            }
+        }
+        parser.finish()?;
+        assert_eq!(expected, actual);
+        Ok(())
+    }
+
+    #[test]
+    fn class_histogram_matches_exact_sample() -> std::io::Result<()> {
+        let options = ReaderOptions {
+            skip_primitive_array_bodies: true,
+            instance_fields: FieldDecoding::None,
+            ..Default::default()
+        };
+        let file = BufReader::new(File::open("heap.hprof")?);
+        let mut hprof_reader = HprofReader::<_, U8>::with_options(file, options)?;
+        let options = SamplingOptions {
+            rate: 1.0,
+            ..Default::default()
+        };
+        let exact = sample_histogram(&mut hprof_reader, &options)?;
+
+        let options = ReaderOptions {
+            instance_fields: FieldDecoding::None,
+            ..Default::default()
+        };
+        let file = BufReader::new(File::open("heap.hprof")?);
+        let mut hprof_reader = HprofReader::<_, U8>::with_options(file, options)?;
+        let mut histogram = ClassHistogram::new(hprof_reader.identifier_size());
+        hprof_reader.accept(&mut histogram)?;
+        let entries = histogram.entries(&hprof_reader)?;
+        assert_eq!(entries.len(), exact.classes.len());
+        for entry in entries {
+            let estimate = exact.classes.iter().find(|c| c.key == entry.key).unwrap();
+            assert_eq!(entry.name, estimate.name);
+            assert_eq!(entry.instances as f64, estimate.instances);
+            assert_eq!(entry.bytes as f64, estimate.bytes);
+            if entry.key == ClassKey::PrimitiveArray(BasicType::Byte) {
+                assert_eq!(entry.name.as_deref(), Some("[B"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shallow_sizes_follow_jvm_layout() -> std::io::Result<()> {
+        let layout = JvmLayout::COMPRESSED;
+        assert_eq!(layout.array_size(BasicType::Int, 3), 32);
+        assert_eq!(layout.array_size(BasicType::Byte, 0), 16);
+        assert_eq!(JvmLayout::BITS_32.array_size(BasicType::Long, 1), 24);
+        assert_eq!(JvmLayout::UNCOMPRESSED.array_size(BasicType::Object, 1), 32);
+        let class_pointers = JvmLayout::COMPRESSED_CLASS_POINTERS;
+        assert_eq!(class_pointers.array_size(BasicType::Object, 1), 24);
+
+        assert_eq!(JvmLayout::from_identifier_size(8), layout);
+        let mut total = 0;
+        for_each_object(|heap, object_id| {
+            let object = heap.object(object_id)?.unwrap();
+            let Some(size) = layout.shallow_size(heap.reader(), &object)? else {
+                return Ok(()); // a class dump
+            };
+            assert_eq!(size % 8, 0);
+            total += size;
+            if let HeapDumpTag::HprofGcInstanceDump {
+                class_object_id, ..
+            } = object
+            {
+                let class_name = heap.reader().class_name(class_object_id)?;
+                if class_name.as_deref() == Some("java/lang/String") {
+                    // the header, a reference to the bytes or chars, and the hash
+                    assert_eq!(size, 24);
+                    let uncompressed = JvmLayout::UNCOMPRESSED.shallow_size(heap.reader(), &object);
+                    assert_eq!(uncompressed?, Some(32));
+                }
+            }
+            Ok(())
+        })?;
+
+        let file = File::open("heap.hprof")?;
+        let mut hprof_reader = HprofReader::<_, U8>::new(BufReader::new(file))?;
+        let mut histogram = ClassHistogram::with_layout(layout);
+        hprof_reader.accept(&mut histogram)?;
+        let entries = histogram.entries(&hprof_reader)?;
+        assert_eq!(entries.iter().map(|e| e.bytes).sum::<u64>(), total);
+        Ok(())
+    }
+
+    #[test]
+    fn sampled_histogram_is_close_to_exact() -> std::io::Result<()> {
+        let sample = |unit, rate| -> std::io::Result<_> {
+            let options = ReaderOptions {
+                skip_primitive_array_bodies: true,
+                instance_fields: FieldDecoding::None,
+                ..Default::default()
+            };
+            let file = BufReader::new(File::open("heap.hprof")?);
+            let mut hprof_reader = HprofReader::<_, U8>::with_options(file, options)?;
+            let options = SamplingOptions {
+                unit,
+                rate,
+                ..Default::default()
+            };
+            sample_histogram(&mut hprof_reader, &options)
+        };
+        let exact = sample(SamplingUnit::Segment, 1.0)?;
+        assert_eq!(exact.total_bytes_error, 0.0);
+        assert_eq!(exact.sampled_units, exact.total_units);
+        assert!(exact.classes.iter().any(|c| c.name.as_deref() == Some("java/lang/String")));
+        assert_eq!(sample(SamplingUnit::SubRecord, 1.0)?.total_bytes, exact.total_bytes);
+        let sampled = sample(SamplingUnit::SubRecord, 0.2)?;
+        assert!(sampled.sampled_units < sampled.total_units);
+        let deviation = (sampled.total_bytes - exact.total_bytes).abs();
+        assert!(deviation <= 5.0 * sampled.total_bytes_error);
+
+        let file = BufReader::new(File::open("heap.hprof")?);
+        let mut hprof_reader = HprofReader::<_, U8>::new(file)?;
+        let sampled = sample_histogram(&mut hprof_reader, &SamplingOptions::default())?;
+        assert!(sampled.sampled_units > 0);
+        let deviation = (sampled.total_bytes - exact.total_bytes).abs();
+        assert!(deviation <= 5.0 * sampled.total_bytes_error);
+        // the dump has too few segments to estimate the error
+        let segments = sample(SamplingUnit::Segment, 0.1)?;
+        assert!(segments.sampled_units >= 2 || segments.total_bytes_error.is_infinite());
+        Ok(())
+    }
+
+    #[test]
+    fn storage_backends_keep_all_entries() -> std::io::Result<()> {
+        let storages = [
+            TableStorage::Arena,
+            TableStorage::SortedVec,
+            TableStorage::Spill {
+                directory: std::env::temp_dir(),
+            },
+        ];
+        for storage in storages {
+            let options = ReaderOptions {
+                string_storage: storage.clone(),
+                class_storage: storage,
+                ..Default::default()
+            };
+            let file = File::open("heap.hprof")?;
+            let mut hprof_reader =
+                HprofReader::<_, U8>::with_options(BufReader::new(file), options)?;
+            let mut strings = vec![];
+            for res in hprof_reader.by_ref() {
+                if let RecordTag::HprofUtf8 { id, utf8, .. } = res? {
+                    strings.push((id, utf8));
+                }
+            }
+            for (id, utf8) in strings {
+                assert_eq!(hprof_reader.name(id)?.as_deref(), Some(&*utf8));
+            }
+        }
+        Ok(())
+    }
 }
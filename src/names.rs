@@ -0,0 +1,130 @@
+use crate::hprof_model::BasicType;
+
+/// Converts a class name in JVM form, like `java/util/Map$Entry`, to the name that
+/// `Class.getName()` returns, like `java.util.Map$Entry`,
+/// `[Ljava.lang.String;`, `[I` or `Foo$$Lambda$14/0x0000000800c03000`.
+pub fn binary_name(name: &str) -> String {
+    match split_array(name) {
+        (0, _) => binary_class_name(name),
+        (dimensions, Element::Class(class)) => {
+            format!("{}L{};", "[".repeat(dimensions), binary_class_name(class))
+        }
+        (_, Element::Primitive(_)) => name.to_string(),
+    }
+}
+
+/// Returns the name as it would be written in Java source, like `java.util.Map.Entry`
+/// or `int[][]`.
+///
+/// Only `$` between two parts of a name is taken as the separator of a nested class, so
+/// anonymous classes keep their name, like `Foo$1`. The suffix of hidden classes is dropped.
+pub fn source_name(name: &str) -> String {
+    let (dimensions, element) = split_array(name);
+    let mut source = match element {
+        Element::Class(class) => source_class_name(class),
+        Element::Primitive(basic_type) => basic_type.keyword().unwrap_or_default().to_string(),
+    };
+    source.push_str(&"[]".repeat(dimensions));
+    source
+}
+
+/// Returns the source name without its package and enclosing classes, like `Entry`
+/// or `String[]`.
+pub fn simple_name(name: &str) -> String {
+    let source = source_name(name);
+    match source.rfind('.') {
+        Some(i) => source[i + 1..].to_string(),
+        None => source,
+    }
+}
+
+/// Whether the class, or the element class of an array class, is a hidden class.
+///
+/// Hidden classes, like the classes of lambdas, carry a suffix that makes their name unique,
+/// like `Foo$$Lambda$14/0x0000000800c03000`. Depending on the JVM, the suffix is separated
+/// by `/` or `+`, and older JVMs use a decimal number.
+pub fn is_hidden(name: &str) -> bool {
+    match split_array(name) {
+        (_, Element::Class(class)) => split_hidden(class).1.is_some(),
+        (_, Element::Primitive(_)) => false,
+    }
+}
+
+enum Element<'a> {
+    Class(&'a str),
+    Primitive(BasicType),
+}
+
+/// Splits a name into the number of array dimensions and the element type.
+fn split_array(name: &str) -> (usize, Element<'_>) {
+    let dimensions = name.bytes().take_while(|&b| b == b'[').count();
+    if dimensions == 0 {
+        return (0, Element::Class(name));
+    }
+    let element = &name[dimensions..];
+    if let Some(class) = element.strip_prefix('L').and_then(|e| e.strip_suffix(';')) {
+        return (dimensions, Element::Class(class));
+    }
+    let mut chars = element.chars();
+    match (
+        chars.next().and_then(BasicType::from_descriptor),
+        chars.next(),
+    ) {
+        (Some(basic_type), None) if basic_type.is_primitive() => {
+            (dimensions, Element::Primitive(basic_type))
+        }
+        // not a valid descriptor, so keep it as it is
+        _ => (dimensions, Element::Class(element)),
+    }
+}
+
+/// Splits the name of a class into the name without the suffix of hidden classes
+/// and the suffix.
+fn split_hidden(class: &str) -> (&str, Option<&str>) {
+    if let Some((name, suffix)) = class.split_once('+') {
+        return (name, Some(suffix));
+    }
+    match class.rsplit_once('/') {
+        Some((name, suffix)) if is_hidden_suffix(suffix) => (name, Some(suffix)),
+        _ => (class, None),
+    }
+}
+
+/// Package names cannot start with a digit, so the last part of a name is the suffix
+/// of a hidden class if it is a number.
+fn is_hidden_suffix(suffix: &str) -> bool {
+    match suffix.strip_prefix("0x") {
+        Some(hex) => !hex.is_empty() && hex.bytes().all(|b| b.is_ascii_hexdigit()),
+        None => !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit()),
+    }
+}
+
+fn binary_class_name(class: &str) -> String {
+    let (name, suffix) = split_hidden(class);
+    let mut binary = name.replace('/', ".");
+    if let Some(suffix) = suffix {
+        binary.push('/');
+        binary.push_str(suffix);
+    }
+    binary
+}
+
+fn source_class_name(class: &str) -> String {
+    let (name, _) = split_hidden(class);
+    let bytes = name.as_bytes();
+    let mut source = String::with_capacity(name.len());
+    for (i, c) in name.char_indices() {
+        let nested_separator = c == '$'
+            && i > 0
+            && !matches!(bytes[i - 1], b'$' | b'/')
+            && bytes
+                .get(i + 1)
+                .is_some_and(|&next| next != b'$' && !next.is_ascii_digit());
+        match c {
+            '/' => source.push('.'),
+            '$' if nested_separator => source.push('.'),
+            _ => source.push(c),
+        }
+    }
+    source
+}
@@ -0,0 +1,81 @@
+use crate::hprof_model::{ObjectId, U4, U8};
+use std::io::{Error, ErrorKind};
+
+/// Maps the sparse object ids of a heap dump to dense indices `0..len`, so that analyses can
+/// keep per-object data in plain vectors and bitsets.
+///
+/// The ids are kept sorted and looked up with a binary search. Next to each id, the offset
+/// of its sub-record in the (decompressed) heap dump is kept, so that objects can be read
+/// again with [`crate::reader::HprofReader::read_object_at`]. This takes 16 bytes per object.
+pub struct ObjectIndex {
+    ids: Vec<ObjectId>,
+    offsets: Vec<U8>,
+}
+
+impl ObjectIndex {
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Returns the dense index of the object with the given id.
+    pub fn index_of(&self, object_id: ObjectId) -> Option<U4> {
+        self.ids.binary_search(&object_id).ok().map(|i| i as U4)
+    }
+
+    /// Returns the id of the object with the given dense index.
+    pub fn id(&self, index: U4) -> ObjectId {
+        self.ids[index as usize]
+    }
+
+    /// Returns the offset of the sub-record of the object with the given dense index.
+    pub fn offset(&self, index: U4) -> U8 {
+        self.offsets[index as usize]
+    }
+
+    /// Returns the offset of the sub-record of the object with the given id.
+    pub fn offset_of(&self, object_id: ObjectId) -> Option<U8> {
+        self.index_of(object_id).map(|i| self.offset(i))
+    }
+
+    /// All object ids, sorted. The position of an id is its dense index.
+    pub fn ids(&self) -> &[ObjectId] {
+        &self.ids
+    }
+}
+
+/// Collects object ids in any order and builds an [`ObjectIndex`] from them.
+#[derive(Default)]
+pub struct ObjectIndexBuilder {
+    entries: Vec<(ObjectId, U8)>,
+}
+
+impl ObjectIndexBuilder {
+    /// Adds an object with the offset of its sub-record.
+    pub fn add(&mut self, object_id: ObjectId, offset: U8) {
+        self.entries.push((object_id, offset));
+    }
+
+    /// The objects added so far, in the order they were added.
+    pub(crate) fn entries(&self) -> &[(ObjectId, U8)] {
+        &self.entries
+    }
+
+    /// Sorts the ids. If an id was added more than once, the first offset is kept.
+    pub fn build(mut self) -> Result<ObjectIndex, Error> {
+        // the sort is stable, so the first offset of each id comes first
+        self.entries.sort_by_key(|&(id, _)| id);
+        self.entries.dedup_by_key(|&mut (id, _)| id);
+        if self.entries.len() > U4::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "too many objects for 32-bit indices",
+            ));
+        }
+        let (ids, offsets) = self.entries.into_iter().unzip();
+        Ok(ObjectIndex { ids, offsets })
+    }
+}
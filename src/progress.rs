@@ -0,0 +1,59 @@
+use crate::hprof_model::RecordKind;
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter};
+use std::io::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Passed to the callback set with [`crate::reader::HprofReader::set_progress_callback`].
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// The number of bytes of the (decompressed) heap dump read so far.
+    pub bytes_consumed: u64,
+    /// The size of the heap dump, if known. It is unknown for compressed input.
+    pub total_bytes: Option<u64>,
+    /// The kind of the record that is currently being read, `None` for unknown tags.
+    pub record_kind: Option<RecordKind>,
+}
+
+/// Cancels a running [`crate::reader::HprofReader`] from another thread.
+///
+/// The reader checks the token between records and sub-records and returns
+/// a [`Cancelled`] error once it was cancelled.
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// The error returned by a reader after its [`CancellationToken`] was cancelled.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("reading the heap dump was cancelled")
+    }
+}
+
+impl StdError for Cancelled {}
+
+impl Cancelled {
+    /// Returns `true` if `error` was caused by a cancellation.
+    pub fn is_cancelled(error: &Error) -> bool {
+        error.get_ref().is_some_and(|e| e.is::<Cancelled>())
+    }
+}
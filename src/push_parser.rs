@@ -0,0 +1,309 @@
+use crate::gzip::GZIP_MAGIC;
+use crate::hprof_model::{BasicType, HeapDumpTag, Identifier, RecordTag, StringId, U1, U4, U8};
+use crate::reader::{unknown_sub_record, AnyHprofReader, HprofReader, ReaderOptions};
+use crate::reader::{HEADER_SIZE, RECORD_HEADER_SIZE};
+use std::borrow::Cow;
+use std::io::{Cursor, Error, ErrorKind};
+
+/// A record or sub-record parsed by a [`PushParser`].
+pub enum ParseEvent {
+    /// A top-level record. Heap dump segments are returned with empty `sub_records`
+    /// as soon as their header is complete, and their sub-records follow one by one.
+    Record(RecordTag),
+    SubRecord(HeapDumpTag),
+}
+
+/// The records and sub-records completed by a chunk passed to [`PushParser::feed`].
+pub struct Parsed {
+    pub events: Vec<ParseEvent>,
+    /// How many more bytes are needed at least to complete the next record or sub-record.
+    pub bytes_needed: usize,
+}
+
+/// Parses a heap dump that is pushed in chunks of any size, without doing any I/O itself.
+///
+/// Only the current record or sub-record is buffered, so heap dump segments are never held
+/// in memory completely. Gzip-compressed input must be decompressed before it is fed.
+pub struct PushParser {
+    /// Taken once the file header is complete
+    options: Option<ReaderOptions>,
+    /// Buffers the file header
+    header: Vec<u8>,
+    /// Parses the buffered bytes, which are kept in its input
+    core: Option<AnyHprofReader<Cursor<Vec<u8>>>>,
+    /// The number of bytes left in the current heap dump segment
+    segment_remaining: U8,
+}
+
+impl PushParser {
+    pub fn new() -> PushParser {
+        Self::with_options(ReaderOptions::default())
+    }
+
+    /// [`ReaderOptions::index_objects`] is ignored, since the parser does not know the
+    /// offsets of the objects in the heap dump.
+    pub fn with_options(options: ReaderOptions) -> PushParser {
+        PushParser {
+            options: Some(ReaderOptions {
+                index_objects: false,
+                ..options
+            }),
+            header: Vec::with_capacity(HEADER_SIZE),
+            core: None,
+            segment_remaining: 0,
+        }
+    }
+
+    /// The identifier size, once the file header is complete.
+    pub fn identifier_size(&self) -> Option<U4> {
+        self.core.as_ref().map(|core| core.identifier_size())
+    }
+
+    /// The timestamp of the heap dump, once the file header is complete.
+    pub fn timestamp(&self) -> Option<U8> {
+        self.core.as_ref().map(|core| core.timestamp())
+    }
+
+    /// Returns the string with the given id, if it was parsed already.
+    pub fn name(&self, id: StringId) -> Result<Option<Cow<'_, str>>, Error> {
+        match &self.core {
+            Some(AnyHprofReader::U4(core)) => core.name(id),
+            Some(AnyHprofReader::U8(core)) => core.name(id),
+            None => Ok(None),
+        }
+    }
+
+    /// Appends `chunk` to the buffered input and parses all records and sub-records
+    /// that are complete now.
+    pub fn feed(&mut self, mut chunk: &[u8]) -> Result<Parsed, Error> {
+        if self.core.is_none() {
+            let n = chunk.len().min(HEADER_SIZE - self.header.len());
+            self.header.extend_from_slice(&chunk[..n]);
+            chunk = &chunk[n..];
+            if self.header.starts_with(&GZIP_MAGIC) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "gzip-compressed input must be decompressed before it is fed",
+                ));
+            }
+            if self.header.len() < HEADER_SIZE {
+                return Ok(Parsed {
+                    events: vec![],
+                    bytes_needed: HEADER_SIZE - self.header.len(),
+                });
+            }
+            let header = std::mem::take(&mut self.header);
+            let options = self.options.take().unwrap_or_default();
+            self.core = Some(AnyHprofReader::with_options(Cursor::new(header), options)?);
+        }
+        match self.core.as_mut().unwrap() {
+            AnyHprofReader::U4(core) => parse(core, &mut self.segment_remaining, chunk),
+            AnyHprofReader::U8(core) => parse(core, &mut self.segment_remaining, chunk),
+        }
+    }
+
+    /// Fails if the input ended in the middle of the header, a record or a sub-record.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        let input = match &mut self.core {
+            Some(AnyHprofReader::U4(core)) => core.input_mut(),
+            Some(AnyHprofReader::U8(core)) => core.input_mut(),
+            None => return Err(Error::from(ErrorKind::UnexpectedEof)),
+        };
+        let incomplete =
+            self.segment_remaining > 0 || input.position() < input.get_ref().len() as U8;
+        if incomplete {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PushParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Appends `chunk` to the input of `core` and parses all records and sub-records that are
+/// complete now.
+fn parse<I: Identifier>(
+    core: &mut HprofReader<Cursor<Vec<u8>>, I>,
+    segment_remaining: &mut U8,
+    chunk: &[u8],
+) -> Result<Parsed, Error> {
+    let input = core.input_mut();
+    let consumed = input.position() as usize;
+    input.get_mut().drain(..consumed);
+    input.get_mut().extend_from_slice(chunk);
+    input.set_position(0);
+    let mut events = vec![];
+    loop {
+        let input = core.input_mut();
+        let start = input.position() as usize;
+        let available = &input.get_ref()[start..];
+        let size = if *segment_remaining > 0 {
+            sub_record_size(available, I::size() as U4)?
+        } else {
+            record_size(available)
+        };
+        let size = match size {
+            // checked before waiting for the bytes of a sub-record with a malformed size
+            Ok(size) | Err(size) if *segment_remaining > 0 && size as U8 > *segment_remaining => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "sub-record exceeds its heap dump segment",
+                ));
+            }
+            Ok(size) if size <= available.len() => size,
+            Ok(size) | Err(size) => {
+                return Ok(Parsed {
+                    events,
+                    bytes_needed: size - available.len(),
+                })
+            }
+        };
+        if *segment_remaining > 0 {
+            *segment_remaining -= size as U8;
+            let sub_record = core.read_sub_record();
+            if sub_record.is_err() {
+                // the next chunk continues with the next sub-record
+                core.input_mut().set_position((start + size) as U8);
+            }
+            if let Some(sub_record) = sub_record? {
+                events.push(ParseEvent::SubRecord(sub_record));
+            }
+            continue;
+        }
+        let Some((tag, base)) = core.read_record_header()? else {
+            return Err(Error::new(ErrorKind::InvalidData, "missing record header"));
+        };
+        let record = if tag == 0x1C {
+            *segment_remaining = base.size_remaining as U8;
+            RecordTag::HprofHeapDumpSegment {
+                base,
+                sub_records: vec![],
+            }
+        } else {
+            // the whole record is buffered, so reading past it means that it is malformed
+            let record = core.read_record(tag, base).map_err(|e| match e.kind() {
+                ErrorKind::UnexpectedEof => Error::new(
+                    ErrorKind::InvalidData,
+                    "record is longer than its declared size",
+                ),
+                _ => e,
+            })?;
+            if core.input_mut().position() as usize != start + size {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "record is shorter than its declared size",
+                ));
+            }
+            record
+        };
+        events.push(ParseEvent::Record(record));
+    }
+}
+
+/// Returns the size of the next top-level record, counting only the header of heap dump
+/// segments, or the number of bytes needed to know it.
+fn record_size(bytes: &[u8]) -> Result<usize, usize> {
+    let mut bytes = Bytes { bytes, position: 0 };
+    let tag = bytes.u1()?;
+    bytes.skip(4)?;
+    let body_size = bytes.u4()?;
+    if tag == 0x1C {
+        return Ok(RECORD_HEADER_SIZE);
+    }
+    Ok(RECORD_HEADER_SIZE + body_size as usize)
+}
+
+/// Returns the size of the next sub-record, including its tag, or the number of bytes
+/// needed to know it.
+fn sub_record_size(bytes: &[u8], identifier_size: U4) -> Result<Result<usize, usize>, Error> {
+    let mut bytes = Bytes { bytes, position: 0 };
+    let Ok(tag) = bytes.u1() else {
+        return Ok(Err(1));
+    };
+    let id = identifier_size as usize;
+    let size = match tag {
+        0x01 => bytes.skip(2 * id),
+        0x02 | 0x03 | 0x08 => bytes.skip(id + 8),
+        0x04 | 0x06 => bytes.skip(id + 4),
+        0x05 | 0x07 | 0xFF => bytes.skip(id),
+        0x20 => class_dump_size(&mut bytes, identifier_size),
+        0x21 => bytes
+            .skip(2 * id + 4)
+            .and_then(|_| bytes.u4())
+            .and_then(|size| bytes.skip(size as usize)),
+        0x22 => bytes
+            .skip(id + 4)
+            .and_then(|_| bytes.u4())
+            .and_then(|count| bytes.skip(id.saturating_mul(count as usize + 1))),
+        0x23 => bytes.skip(id + 4).and_then(|_| {
+            let count = bytes.u4()?;
+            let type_tag = bytes.u1()?;
+            bytes.skip(value_size(type_tag, identifier_size).saturating_mul(count as usize))
+        }),
+        _ => return Err(unknown_sub_record(tag)),
+    };
+    Ok(size.map(|_| bytes.position))
+}
+
+fn class_dump_size(bytes: &mut Bytes, identifier_size: U4) -> Result<(), usize> {
+    let id = identifier_size as usize;
+    bytes.skip(7 * id + 8)?;
+    let constant_pool_size = bytes.u2()?;
+    for _ in 0..constant_pool_size {
+        bytes.skip(2)?;
+        let type_tag = bytes.u1()?;
+        bytes.skip(value_size(type_tag, identifier_size))?;
+    }
+    let static_fields_count = bytes.u2()?;
+    for _ in 0..static_fields_count {
+        bytes.skip(id)?;
+        let type_tag = bytes.u1()?;
+        bytes.skip(value_size(type_tag, identifier_size))?;
+    }
+    let instance_field_count = bytes.u2()?;
+    bytes.skip((id + 1) * instance_field_count as usize)
+}
+
+/// The size of a value with the given type tag. Invalid tags are reported when the
+/// sub-record is parsed.
+fn value_size(type_tag: U1, identifier_size: U4) -> usize {
+    BasicType::try_from(type_tag).map_or(0, |t| t.size(identifier_size) as usize)
+}
+
+/// Walks over buffered bytes. Fails with the number of bytes needed to continue.
+struct Bytes<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Bytes<'_> {
+    fn skip(&mut self, n: usize) -> Result<(), usize> {
+        self.position = self.position.saturating_add(n);
+        if self.position > self.bytes.len() {
+            return Err(self.position);
+        }
+        Ok(())
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], usize> {
+        let start = self.position;
+        self.skip(N)?;
+        Ok(self.bytes[start..self.position].try_into().unwrap())
+    }
+
+    fn u1(&mut self) -> Result<U1, usize> {
+        self.take::<1>().map(|b| b[0])
+    }
+
+    fn u2(&mut self) -> Result<u16, usize> {
+        self.take().map(u16::from_be_bytes)
+    }
+
+    fn u4(&mut self) -> Result<U4, usize> {
+        self.take().map(U4::from_be_bytes)
+    }
+}
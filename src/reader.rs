@@ -1,22 +1,150 @@
+use crate::checkpoint;
+use crate::checkpoint::CheckpointState;
+use crate::gzip::{GzipReader, GZIP_MAGIC};
 use crate::hprof_model;
 use crate::hprof_model::HeapDumpTag::HprofGcPrimArrayDump;
 use crate::hprof_model::{
-    ClassInfo, FieldInfo, HeapDumpTag, RecordBase, RecordTag, Value, I4, U2, U4, U8,
+    BasicType, ClassInfo, FieldInfo, HeapDumpTag, Identifier, PrimitiveArray, RecordBase,
+    RecordKind, RecordTag, SubRecordKind, Value, I4, U2, U4, U8,
 };
+use crate::hprof_model::{
+    ClassId, ClassSerial, FrameId, ObjectId, StringId, ThreadSerial, TraceSerial,
+};
+use crate::instance::Instance;
+use crate::layout::{decode_value, ClassLayout, LayoutCache};
+use crate::object_index::{ObjectIndex, ObjectIndexBuilder};
+use crate::progress::{CancellationToken, Cancelled, Progress};
+use crate::stack_trace::{FrameInfo, LineNumber, StackFrame, StackTrace, StackTraceTable};
+use crate::storage::{Table, TableStorage};
+use crate::visitor;
+use crate::visitor::{HprofVisitor, Visit};
 use hprof_model::U1;
-use std::collections::HashMap;
-use std::io::{Error, ErrorKind, Read, Seek};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// How many bytes are read between two calls of the progress callback within a record.
+const PROGRESS_INTERVAL: U8 = 1 << 20;
+
+/// How many class layouts are cached if the classes are not kept in a
+/// [`TableStorage::HashMap`]. Layouts that were not used recently are evicted first.
+const LAYOUT_CACHE_SIZE: usize = 4096;
+
+/// The size of the file header, including the identifier size and the timestamp.
+pub(crate) const HEADER_SIZE: usize = 31;
 
-struct InternalHprofReader<T: Read + Seek> {
-    buf_reader: T,
+/// The size of the tag, the timestamp and the length of a top-level record.
+pub(crate) const RECORD_HEADER_SIZE: usize = 9;
+
+struct InternalHprofReader<T: Read + Seek, I: Identifier> {
+    buf_reader: Input<T>,
+    identifier: PhantomData<I>,
+}
+
+/// The input of a [`HprofReader`], decompressed if needed.
+enum Input<T: Read + Seek> {
+    Plain(T),
+    Gzip(Box<GzipReader<T>>),
 }
 
-pub struct HprofReader<T: Read + Seek> {
-    pub identifier_size: U4,
+/// Controls how much of the heap dump [`HprofReader`] decodes.
+///
+/// Everything that is not decoded is skipped over without being parsed.
+#[derive(Clone, Default)]
+pub struct ReaderOptions {
+    /// Only read the length and element type of primitive arrays,
+    /// leaving [`HeapDumpTag::HprofGcPrimArrayDump::elements`] empty.
+    pub skip_primitive_array_bodies: bool,
+    /// Which field values of instances to decode.
+    pub instance_fields: FieldDecoding,
+    /// Sub-records of these kinds are not returned from heap dump segments.
+    /// Class dumps are still read to be able to decode instances.
+    pub skipped_sub_records: HashSet<SubRecordKind>,
+    /// Where the strings of `HPROF_UTF8` records are kept.
+    pub string_storage: TableStorage,
+    /// Where the class dumps are kept, which are needed to decode instances. The instance
+    /// field layouts computed from them are cached for all classes with
+    /// [`TableStorage::HashMap`], and only for a bounded number of classes otherwise.
+    pub class_storage: TableStorage,
+    /// Record the id and offset of each object that is read, including skipped ones, to
+    /// build an [`ObjectIndex`] without a second pass. See [`HprofReader::index_objects`].
+    pub index_objects: bool,
+}
+
+/// Which field values [`HprofReader`] decodes for instance dumps.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum FieldDecoding {
+    /// Decode all field values.
+    #[default]
+    All,
+    /// Only decode object references, in field order. Primitive values are skipped.
+    ReferencesOnly,
+    /// Decode no field values at all.
+    None,
+}
+
+/// Reads a heap dump whose identifiers are `I`, either [`U4`] or [`U8`].
+///
+/// Use [`AnyHprofReader`] if the identifier size is not known before the header is read.
+pub struct HprofReader<T: Read + Seek, I: Identifier> {
     pub timestamp: U8,
-    reader: InternalHprofReader<T>,
-    name_cache: HashMap<U8, String>,
-    class_cache: HashMap<U8, ClassInfo>,
+    reader: InternalHprofReader<T, I>,
+    options: ReaderOptions,
+    /// The segment that is currently being read and the position where it ends
+    current_segment: Option<(RecordBase, U8)>,
+    progress_callback: Option<Box<dyn FnMut(Progress) + Send>>,
+    total_bytes: Option<U8>,
+    next_progress_report: U8,
+    /// The record kind of the last progress report, `None` before the first one
+    reported_kind: Option<Option<RecordKind>>,
+    /// The size of the records read since the last progress report, not counting the
+    /// sub-records of segments
+    unreported_bytes: U8,
+    cancellation_token: Option<CancellationToken>,
+    name_cache: Box<dyn Table<str>>,
+    class_cache: Box<dyn Table<ClassInfo>>,
+    /// The name ids of `HPROF_LOAD_CLASS` records by class object id
+    class_names: HashMap<ClassId, StringId>,
+    stack_traces: StackTraceTable,
+    /// Reused to read the bytes of `HPROF_UTF8` records
+    utf8_buffer: Vec<U1>,
+    /// The computed class layouts, bounded by [`LAYOUT_CACHE_SIZE`] depending on the
+    /// class storage
+    layouts: LayoutCache,
+    /// Reused to read the field data of instance dumps and the elements of primitive arrays
+    field_buffer: Vec<U1>,
+    /// Reused to pass the field values of instances to visitors
+    value_buffer: Vec<Value>,
+    /// Reused to pass the elements of object arrays to visitors
+    element_buffer: Vec<ObjectId>,
+    /// Reused to pass the elements of primitive arrays to visitors
+    primitive_array_buffer: PrimitiveArray,
+    /// The objects read so far, if [`ReaderOptions::index_objects`] is set
+    object_index: Option<ObjectIndexBuilder>,
+}
+
+macro_rules! from_be_chunks {
+    ($bytes:expr, $type:ident) => {
+        $bytes
+            .chunks_exact(size_of::<$type>())
+            .map(|c| $type::from_be_bytes(c.try_into().unwrap()))
+    };
+}
+
+/// Replaces the elements of `$array` with `$values`, keeping its allocation if it has the
+/// variant `$variant` already.
+macro_rules! replace_elements {
+    ($array:expr, $variant:ident, $values:expr) => {
+        match $array {
+            PrimitiveArray::$variant(elements) => {
+                elements.clear();
+                elements.extend($values);
+            }
+            array => *array = PrimitiveArray::$variant($values.collect()),
+        }
+    };
 }
 
 macro_rules! define_read_ux {
@@ -30,27 +158,417 @@ macro_rules! define_read_ux {
     };
 }
 
-impl<T: Read + Seek> HprofReader<T> {
-    pub fn new(buf_reader: T) -> Result<HprofReader<T>, Error> {
-        let internal = InternalHprofReader::new(buf_reader);
-        internal.read_hprof()
+/// A [`HprofReader`] for the identifier size declared in the header of a heap dump.
+///
+/// The identifier size is only dispatched on once, so callers match on the variants and
+/// continue with code that is generic over the identifier type.
+pub enum AnyHprofReader<T: Read + Seek> {
+    U4(HprofReader<T, U4>),
+    U8(HprofReader<T, U8>),
+}
+
+impl<T: Read + Seek> AnyHprofReader<T> {
+    pub fn new(buf_reader: T) -> Result<AnyHprofReader<T>, Error> {
+        Self::with_options(buf_reader, ReaderOptions::default())
+    }
+
+    /// Reads the header and creates the reader for its identifier size.
+    ///
+    /// Gzip-compressed heap dumps are detected and decompressed transparently.
+    pub fn with_options(buf_reader: T, options: ReaderOptions) -> Result<AnyHprofReader<T>, Error> {
+        let mut input = Input::new(buf_reader)?;
+        let (identifier_size, timestamp) = read_header(&mut input)?;
+        let reader = match identifier_size {
+            4 => AnyHprofReader::U4(HprofReader::from_header(input, timestamp, options)?),
+            _ => AnyHprofReader::U8(HprofReader::from_header(input, timestamp, options)?),
+        };
+        Ok(reader)
+    }
+
+    pub fn identifier_size(&self) -> U4 {
+        match self {
+            AnyHprofReader::U4(reader) => reader.identifier_size(),
+            AnyHprofReader::U8(reader) => reader.identifier_size(),
+        }
+    }
+
+    pub fn timestamp(&self) -> U8 {
+        match self {
+            AnyHprofReader::U4(reader) => reader.timestamp,
+            AnyHprofReader::U8(reader) => reader.timestamp,
+        }
+    }
+}
+
+impl<T: Read + Seek, I: Identifier> HprofReader<T, I> {
+    /// Creates a reader for a heap dump that is known to have identifiers of type `I`.
+    /// Fails if the header declares another identifier size.
+    pub fn new(buf_reader: T) -> Result<HprofReader<T, I>, Error> {
+        Self::with_options(buf_reader, ReaderOptions::default())
+    }
+
+    /// Like [`HprofReader::new`], with the given options.
+    ///
+    /// Gzip-compressed heap dumps are detected and decompressed transparently.
+    pub fn with_options(buf_reader: T, options: ReaderOptions) -> Result<HprofReader<T, I>, Error> {
+        let mut input = Input::new(buf_reader)?;
+        let (identifier_size, timestamp) = read_header(&mut input)?;
+        if identifier_size as usize != I::size() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "expected identifiers of {} bytes, not {identifier_size}",
+                    I::size()
+                ),
+            ));
+        }
+        Self::from_header(input, timestamp, options)
+    }
+
+    fn from_header(
+        input: Input<T>,
+        timestamp: U8,
+        options: ReaderOptions,
+    ) -> Result<HprofReader<T, I>, Error> {
+        Ok(HprofReader {
+            timestamp,
+            reader: InternalHprofReader {
+                buf_reader: input,
+                identifier: PhantomData,
+            },
+            current_segment: None,
+            progress_callback: None,
+            total_bytes: None,
+            next_progress_report: 0,
+            reported_kind: None,
+            unreported_bytes: 0,
+            cancellation_token: None,
+            name_cache: options.string_storage.create()?,
+            class_cache: options.class_storage.create()?,
+            class_names: HashMap::new(),
+            stack_traces: StackTraceTable::default(),
+            utf8_buffer: vec![],
+            layouts: LayoutCache::new(
+                (!matches!(options.class_storage, TableStorage::HashMap))
+                    .then_some(LAYOUT_CACHE_SIZE),
+            ),
+            field_buffer: vec![],
+            value_buffer: vec![],
+            element_buffer: vec![],
+            primitive_array_buffer: PrimitiveArray::Byte(vec![]),
+            object_index: options.index_objects.then(ObjectIndexBuilder::default),
+            options,
+        })
+    }
+
+    /// The size of the identifiers in the heap dump, 4 or 8.
+    pub fn identifier_size(&self) -> U4 {
+        I::size() as U4
+    }
+
+    /// Creates a reader that continues where the reader that wrote `checkpoint` with
+    /// [`HprofReader::write_checkpoint`] stopped. `buf_reader` must be at the start of
+    /// the same heap dump.
+    pub fn resume(
+        buf_reader: T,
+        options: ReaderOptions,
+        checkpoint: &mut impl Read,
+    ) -> Result<HprofReader<T, I>, Error> {
+        let mut reader = Self::with_options(buf_reader, options)?;
+        let state = checkpoint::read_checkpoint(
+            checkpoint,
+            reader.name_cache.as_mut(),
+            reader.class_cache.as_mut(),
+            &mut reader.class_names,
+            &mut reader.stack_traces,
+            reader.object_index.as_mut(),
+        )?;
+        if state.identifier_size != reader.identifier_size() || state.timestamp != reader.timestamp
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the checkpoint belongs to another heap dump",
+            ));
+        }
+        reader
+            .reader
+            .buf_reader
+            .seek(SeekFrom::Start(state.offset))?;
+        reader.current_segment = state.segment;
+        Ok(reader)
+    }
+
+    /// Writes the position of the reader, including the position inside the current
+    /// heap dump segment, and the strings, classes, class names, stack traces and indexed
+    /// objects read so far to `out`.
+    ///
+    /// The checkpoint can be passed to [`HprofReader::resume`] to continue reading later.
+    pub fn write_checkpoint(&mut self, out: &mut impl Write) -> Result<(), Error> {
+        let state = CheckpointState {
+            identifier_size: self.identifier_size(),
+            timestamp: self.timestamp,
+            offset: self.reader.buf_reader.stream_position()?,
+            segment: self.current_segment,
+        };
+        checkpoint::write_checkpoint(
+            out,
+            &state,
+            self.name_cache.as_ref(),
+            self.class_cache.as_ref(),
+            &self.class_names,
+            &self.stack_traces,
+            self.object_index.as_ref(),
+        )
+    }
+
+    /// Reads the remaining dump and calls the matching method of `visitor` for each record
+    /// and sub-record, without collecting the sub-records of heap dump segments.
+    ///
+    /// Returns early if the visitor returns [`Visit::Stop`]. Calling `accept` or `next`
+    /// afterwards continues where the visitor stopped, even in the middle of a segment.
+    pub fn accept<V: HprofVisitor>(&mut self, visitor: &mut V) -> Result<(), Error> {
+        loop {
+            let visit = if self.current_segment.is_some() {
+                if !self.has_sub_records()? {
+                    continue;
+                }
+                let visit = self.accept_sub_record(visitor);
+                self.leave_segment_on_error(visit)?
+            } else {
+                let Some((tag, base)) = self.read_record_header()? else {
+                    return Ok(()); // eof
+                };
+                match tag {
+                    0x01 => {
+                        let (id, utf8) = self.read_utf8_entry(&base)?;
+                        visitor.visit_utf8(id, &utf8)
+                    }
+                    0x1C => {
+                        self.start_heap_dump_segment(base)?;
+                        visitor.visit_heap_dump_segment(&base)
+                    }
+                    _ => match self.read_record(tag, base)? {
+                        RecordTag::HprofLoadClass {
+                            class_serial_number,
+                            class_object_id,
+                            stack_trace_serial_number,
+                            class_name_id,
+                            ..
+                        } => visitor.visit_load_class(
+                            class_serial_number,
+                            class_object_id,
+                            stack_trace_serial_number,
+                            class_name_id,
+                        ),
+                        record => visitor.visit_record(&record),
+                    },
+                }
+            };
+            match visit {
+                Visit::Continue | Visit::Skip => {}
+                Visit::SkipSegment => {
+                    if let Some((_, end)) = self.current_segment.take() {
+                        let position = self.reader.buf_reader.stream_position()?;
+                        self.reader.skip(end - position)?;
+                    }
+                }
+                Visit::Stop => return Ok(()),
+            }
+        }
+    }
+
+    /// Reads the next sub-record of a segment and passes it to `visitor`. The field values
+    /// and array elements are decoded into buffers that are reused for all sub-records.
+    fn accept_sub_record<V: HprofVisitor>(&mut self, visitor: &mut V) -> Result<Visit, Error> {
+        let (offset, tag, id) = self.read_sub_record_header()?;
+        let kind = SubRecordKind::from_id(tag).expect("the tag was checked");
+        if self.options.skipped_sub_records.contains(&kind) {
+            self.skip_sub_record_rest(tag, id)?;
+            return Ok(Visit::Continue);
+        }
+        match visitor.visit_sub_record_header(kind, ObjectId(id)) {
+            Visit::Continue => {}
+            Visit::Skip => {
+                self.skip_sub_record_rest(tag, id)?;
+                return Ok(Visit::Continue);
+            }
+            Visit::SkipSegment => return Ok(Visit::SkipSegment),
+            Visit::Stop => {
+                // the sub-record is read again when `accept` is called the next time
+                self.reader.buf_reader.seek(SeekFrom::Start(offset))?;
+                return Ok(Visit::Stop);
+            }
+        }
+        let visit = match tag {
+            0x21 => {
+                let stack_trace_serial_number = TraceSerial(self.reader.read_u4()?);
+                let class_object_id = ClassId(self.reader.read_identifier()?);
+                let size = self.reader.read_u4()?;
+                let mut values = std::mem::take(&mut self.value_buffer);
+                self.read_instance_fields(class_object_id, size, &mut values)?;
+                let visit = visitor.visit_instance(
+                    ObjectId(id),
+                    stack_trace_serial_number,
+                    class_object_id,
+                    &values,
+                );
+                self.value_buffer = values;
+                visit
+            }
+            0x22 => {
+                let stack_trace_serial_number = TraceSerial(self.reader.read_u4()?);
+                let element_count = self.reader.read_u4()?;
+                let array_class_id = ClassId(self.reader.read_identifier()?);
+                self.check_remaining(element_count, I::size() as u64)?;
+                self.element_buffer.clear();
+                for _ in 0..element_count {
+                    let element = ObjectId(self.reader.read_identifier()?);
+                    self.element_buffer.push(element);
+                }
+                visitor.visit_object_array(
+                    ObjectId(id),
+                    stack_trace_serial_number,
+                    array_class_id,
+                    &self.element_buffer,
+                )
+            }
+            0x23 => {
+                let stack_trace_serial_number = TraceSerial(self.reader.read_u4()?);
+                let element_count = self.reader.read_u4()?;
+                let element_type = self.reader.read_basic_type()?;
+                if self.options.skip_primitive_array_bodies {
+                    let size = self.reader.type_size(element_type) * element_count as u64;
+                    self.reader.skip(size)?;
+                    visitor.visit_primitive_array(
+                        ObjectId(id),
+                        stack_trace_serial_number,
+                        element_type,
+                        element_count,
+                        None,
+                    )
+                } else {
+                    let mut elements = std::mem::replace(
+                        &mut self.primitive_array_buffer,
+                        PrimitiveArray::Byte(vec![]),
+                    );
+                    self.read_primitive_array(element_type, element_count, &mut elements)?;
+                    let visit = visitor.visit_primitive_array(
+                        ObjectId(id),
+                        stack_trace_serial_number,
+                        element_type,
+                        element_count,
+                        Some(&elements),
+                    );
+                    self.primitive_array_buffer = elements;
+                    visit
+                }
+            }
+            _ => {
+                let sub_record = self.read_sub_record_rest(tag, id)?;
+                visitor::visit_sub_record(visitor, &sub_record)
+            }
+        };
+        Ok(visit)
+    }
+
+    pub(crate) fn input_mut(&mut self) -> &mut T {
+        match &mut self.reader.buf_reader {
+            Input::Plain(input) => input,
+            Input::Gzip(_) => panic!("input is compressed"),
+        }
     }
 
     fn read_next(&mut self) -> Result<Option<RecordTag>, Error> {
+        if let Some((base, _)) = self.current_segment {
+            return self.read_heap_dump_segment_rest(base).map(Some);
+        }
+        let Some((tag, base)) = self.read_record_header()? else {
+            return Ok(None); // eof
+        };
+        self.read_record(tag, base).map(Some)
+    }
+
+    /// Reads the tag and the base of the next record, or returns `None` at the end of the input.
+    pub(crate) fn read_record_header(&mut self) -> Result<Option<(U1, RecordBase)>, Error> {
         let tag = self.reader.read_u1();
         // TODO better way to detect eof?
         if tag.is_err() {
             return Ok(None); // eof
         }
+        let tag = tag?;
+        self.report_progress(RecordKind::from_id(tag), true)?;
         let base = self.read_base()?;
-        match tag? {
+        if tag != 0x1C {
+            self.unreported_bytes += RECORD_HEADER_SIZE as U8 + base.size_remaining as U8;
+        }
+        Ok(Some((tag, base)))
+    }
+
+    /// Fails if the reader was cancelled and calls the progress callback, at the start of
+    /// a record whose kind differs from the last report, and otherwise at most every
+    /// [`PROGRESS_INTERVAL`] bytes.
+    fn report_progress(
+        &mut self,
+        record_kind: Option<RecordKind>,
+        record_start: bool,
+    ) -> Result<(), Error> {
+        if self
+            .cancellation_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            return Err(Error::other(Cancelled));
+        }
+        if let Some(callback) = &mut self.progress_callback {
+            // dumps have millions of small records, so their position is not even looked up
+            // until a report is due
+            let record_due = self.reported_kind != Some(record_kind)
+                || self.unreported_bytes >= PROGRESS_INTERVAL;
+            if record_start && !record_due {
+                return Ok(());
+            }
+            let bytes_consumed = self.reader.buf_reader.stream_position()?;
+            if record_start || bytes_consumed >= self.next_progress_report {
+                self.next_progress_report = bytes_consumed + PROGRESS_INTERVAL;
+                self.reported_kind = Some(record_kind);
+                self.unreported_bytes = 0;
+                callback(Progress {
+                    bytes_consumed,
+                    total_bytes: self.total_bytes,
+                    record_kind,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets a callback that is regularly called with the progress of the reader.
+    pub fn set_progress_callback<F: FnMut(Progress) + Send + 'static>(
+        &mut self,
+        callback: F,
+    ) -> Result<(), Error> {
+        self.total_bytes = self.reader.buf_reader.len()?;
+        self.progress_callback = Some(Box::new(callback));
+        Ok(())
+    }
+
+    /// Sets a token that makes the reader return a [`Cancelled`] error once it is cancelled.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation_token = Some(token);
+    }
+
+    pub(crate) fn read_record(&mut self, tag: U1, base: RecordBase) -> Result<RecordTag, Error> {
+        match tag {
             0x01 => self.read_utf8(base),
             0x02 => self.read_load_class(base),
             0x04 => self.read_frame(base),
             0x05 => self.read_trace(base),
             0x1C => self.read_heap_dump_segment(base),
             0x2C => self.read_heap_dump_end(base),
-            v => panic!("unsupported tag: {:#x}", v),
+            tag => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported record tag {tag:#x}"),
+            )),
         }
     }
 
@@ -64,127 +582,361 @@ impl<T: Read + Seek> HprofReader<T> {
         Ok(base)
     }
 
-    fn read_utf8(&mut self, base: RecordBase) -> Result<Option<RecordTag>, Error> {
-        let id = self.read_identifier()?;
-        let rem = base.size_remaining - self.identifier_size;
-        let mut utf8: Vec<U1> = vec![0; rem as usize];
-        self.reader.buf_reader.read_exact(&mut utf8)?;
-        // TODO why do we encounter invalid utf8???
-        let string = String::from_utf8_lossy_owned(utf8.clone());
-        // todo deal with lifetime of HprofUtf8/String properly
-        self.name_cache.insert(id, string.clone());
-        Ok(Some(RecordTag::HprofUtf8 {
-            base,
-            id,
-            utf8: string,
-        }))
+    fn read_utf8(&mut self, base: RecordBase) -> Result<RecordTag, Error> {
+        let (id, utf8) = self.read_utf8_entry(&base)?;
+        Ok(RecordTag::HprofUtf8 { base, id, utf8 })
     }
 
-    fn read_identifier(&mut self) -> Result<U8, Error> {
-        let id = match self.identifier_size {
-            4 => self.reader.read_u4()? as U8,
-            8 => self.reader.read_u8()?,
-            _ => panic!("unsupported id size"),
-        };
-        Ok(id)
+    /// Reads the body of a `HPROF_UTF8` record and adds it to the name cache.
+    fn read_utf8_entry(&mut self, base: &RecordBase) -> Result<(StringId, Arc<str>), Error> {
+        let id = StringId(self.reader.read_identifier()?);
+        let rem = base
+            .size_remaining
+            .checked_sub(self.identifier_size())
+            .ok_or(Error::new(
+                ErrorKind::InvalidData,
+                "HPROF_UTF8 record shorter than its id",
+            ))?;
+        let utf8 = &mut self.utf8_buffer;
+        utf8.resize(rem as usize, 0);
+        self.reader.buf_reader.read_exact(utf8)?;
+        // TODO why do we encounter invalid utf8???
+        let string: Arc<str> = Arc::from(String::from_utf8_lossy(utf8));
+        self.name_cache.insert(id.0, &string)?;
+        Ok((id, string))
     }
 
-    fn read_load_class(&mut self, base: RecordBase) -> Result<Option<RecordTag>, Error> {
-        let class_serial_number = self.reader.read_u4()?;
-        let class_object_id = self.read_identifier()?;
-        let stack_trace_serial_number = self.reader.read_u4()?;
-        let class_name_id = self.read_identifier()?;
-        Ok(Some(RecordTag::HprofLoadClass {
+    fn read_load_class(&mut self, base: RecordBase) -> Result<RecordTag, Error> {
+        let class_serial_number = ClassSerial(self.reader.read_u4()?);
+        let class_object_id = ClassId(self.reader.read_identifier()?);
+        let stack_trace_serial_number = TraceSerial(self.reader.read_u4()?);
+        let class_name_id = StringId(self.reader.read_identifier()?);
+        self.class_names.insert(class_object_id, class_name_id);
+        self.stack_traces
+            .class_serials
+            .insert(class_serial_number, class_object_id);
+        Ok(RecordTag::HprofLoadClass {
             base,
             class_serial_number,
             class_object_id,
             stack_trace_serial_number,
             class_name_id,
-        }))
+        })
     }
 
-    fn read_trace(&mut self, base: RecordBase) -> Result<Option<RecordTag>, Error> {
-        let stack_trace_serial_number = self.reader.read_u4()?;
-        let thread_serial_number = self.reader.read_u4()?;
+    fn read_trace(&mut self, base: RecordBase) -> Result<RecordTag, Error> {
+        let stack_trace_serial_number = TraceSerial(self.reader.read_u4()?);
+        let thread_serial_number = ThreadSerial(self.reader.read_u4()?);
         let number_of_frames = self.reader.read_u4()?;
-        let mut stack_frame_ids = vec![0; number_of_frames as usize];
+        let frames_size = number_of_frames as U8 * self.identifier_size() as U8;
+        if frames_size > base.size_remaining.saturating_sub(12) as U8 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "HPROF_TRACE record shorter than its frames",
+            ));
+        }
+        let mut stack_frame_ids = vec![FrameId::default(); number_of_frames as usize];
         for idx in 0..number_of_frames {
-            stack_frame_ids[idx as usize] = self.read_identifier()?;
+            stack_frame_ids[idx as usize] = FrameId(self.reader.read_identifier()?);
         }
-        Ok(Some(RecordTag::HprofTrace {
+        self.stack_traces.traces.insert(
+            stack_trace_serial_number,
+            (thread_serial_number, stack_frame_ids.clone()),
+        );
+        Ok(RecordTag::HprofTrace {
             base,
             stack_trace_serial_number,
             thread_serial_number,
             stack_frame_ids,
-        }))
+        })
     }
 
-    fn read_frame(&mut self, base: RecordBase) -> Result<Option<RecordTag>, Error> {
-        let stack_frame_id: U8 = self.read_identifier()?;
-        let method_name_id: U8 = self.read_identifier()?;
-        let method_signature_id: U8 = self.read_identifier()?;
-        let source_file_name_id: U8 = self.read_identifier()?;
-        let class_serial_numer: U4 = self.reader.read_u4()?;
+    fn read_frame(&mut self, base: RecordBase) -> Result<RecordTag, Error> {
+        let stack_frame_id = FrameId(self.reader.read_identifier()?);
+        let method_name_id = StringId(self.reader.read_identifier()?);
+        let method_signature_id = StringId(self.reader.read_identifier()?);
+        let source_file_name_id = StringId(self.reader.read_identifier()?);
+        let class_serial_number = ClassSerial(self.reader.read_u4()?);
         let line_number: I4 = self.reader.read_u4()? as I4;
-        Ok(Some(RecordTag::HprofFrame {
+        let frame = FrameInfo {
+            method_name_id,
+            method_signature_id,
+            source_file_name_id,
+            class_serial_number,
+            line_number,
+        };
+        self.stack_traces.frames.insert(stack_frame_id, frame);
+        Ok(RecordTag::HprofFrame {
             base,
             stack_frame_id,
             method_name_id,
             method_signature_id,
             source_file_name_id,
-            class_serial_numer,
+            class_serial_number,
             line_number,
-        }))
+        })
+    }
+
+    fn read_heap_dump_segment(&mut self, base: RecordBase) -> Result<RecordTag, Error> {
+        self.start_heap_dump_segment(base)?;
+        self.read_heap_dump_segment_rest(base)
     }
 
-    fn read_heap_dump_segment(&mut self, base: RecordBase) -> Result<Option<RecordTag>, Error> {
+    pub(crate) fn start_heap_dump_segment(&mut self, base: RecordBase) -> Result<(), Error> {
         let end = self.reader.buf_reader.stream_position()? + base.size_remaining as U8;
+        self.current_segment = Some((base, end));
+        Ok(())
+    }
+
+    /// Reads the sub-records from the current position to the end of the current segment.
+    fn read_heap_dump_segment_rest(&mut self, base: RecordBase) -> Result<RecordTag, Error> {
         let mut sub_records = vec![];
-        while self.reader.buf_reader.stream_position()? < end {
-            let id = self.reader.read_u1()?;
-            let s = match id {
-                0x01 => self.read_gc_root_jni_global()?,
-                0x02 => self.read_gc_root_jni_local()?,
-                0x03 => self.read_gc_root_java_frame()?,
-                0x05 => self.read_gc_root_sticky_class()?,
-                0x08 => self.read_gc_root_thread_obj()?,
-                0x20 => self.read_gc_class_dump()?,
-                0x21 => self.read_gc_instance_dump()?,
-                0x22 => self.read_gc_obj_array_dump()?,
-                0x23 => self.read_gc_prim_array_dump()?,
-                _ => panic!("unknown sub-record tag {:#x}", id),
-            };
-            sub_records.push(s);
+        while self.has_sub_records()? {
+            let sub_record = self.read_sub_record();
+            if let Some(s) = self.leave_segment_on_error(sub_record)? {
+                sub_records.push(s);
+            }
+        }
+        Ok(RecordTag::HprofHeapDumpSegment { base, sub_records })
+    }
+
+    /// Returns whether the current segment has sub-records left to read.
+    /// Leaves the segment once it is read completely.
+    pub(crate) fn has_sub_records(&mut self) -> Result<bool, Error> {
+        let Some((_, end)) = self.current_segment else {
+            return Ok(false);
+        };
+        if self.reader.buf_reader.stream_position()? >= end {
+            self.current_segment = None;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Fails if `count` values of `size` bytes do not fit into the rest of the current
+    /// segment, or of the input outside of segments, so that no more is allocated for a
+    /// count than the dump can hold.
+    fn check_remaining(&mut self, count: U4, size: u64) -> Result<(), Error> {
+        let end = match self.current_segment {
+            Some((_, end)) => Some(end),
+            None => self.reader.buf_reader.len()?,
+        };
+        let Some(end) = end else {
+            return Ok(()); // the length of compressed input is not known
+        };
+        let remaining = end.saturating_sub(self.reader.buf_reader.stream_position()?);
+        if count as u64 * size > remaining {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "array is longer than the rest of its heap dump segment",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Skips the rest of the current segment if reading one of its sub-records failed,
+    /// since the start of the next sub-record is not known then. The segment is kept after
+    /// a cancellation, which happens between sub-records.
+    fn leave_segment_on_error<R>(&mut self, result: Result<R, Error>) -> Result<R, Error> {
+        if result.as_ref().is_err_and(|e| !Cancelled::is_cancelled(e)) {
+            if let Some((_, end)) = self.current_segment.take() {
+                // a failing seek fails the next read, too
+                let _ = self.reader.buf_reader.seek(SeekFrom::Start(end));
+            }
+        }
+        result
+    }
+
+    /// Reads the next sub-record of a segment, or skips it and returns `None`
+    /// if its kind is skipped by the options.
+    pub(crate) fn read_sub_record(&mut self) -> Result<Option<HeapDumpTag>, Error> {
+        let (_, tag, id) = self.read_sub_record_header()?;
+        let skipped = SubRecordKind::from_id(tag)
+            .is_some_and(|kind| self.options.skipped_sub_records.contains(&kind));
+        self.read_sub_record_body(tag, id, !skipped)
+    }
+
+    /// Reads the tag and the first identifier of the next sub-record of a segment, and
+    /// returns them with the offset of the sub-record. Objects are added to the index.
+    pub(crate) fn read_sub_record_header(&mut self) -> Result<(U8, U1, U8), Error> {
+        let offset = self.reader.buf_reader.stream_position()?;
+        let tag = self.read_sub_record_tag()?;
+        if SubRecordKind::from_id(tag).is_none() {
+            return Err(unknown_sub_record(tag));
+        }
+        let id = self.reader.read_identifier()?;
+        if let Some(object_index) = &mut self.object_index {
+            if (0x20..=0x23).contains(&tag) {
+                object_index.add(ObjectId(id), offset);
+            }
+        }
+        Ok((offset, tag, id))
+    }
+
+    fn read_sub_record_tag(&mut self) -> Result<U1, Error> {
+        self.report_progress(Some(RecordKind::HprofHeapDumpSegment), false)?;
+        self.reader.read_u1()
+    }
+
+    /// Reads the rest of the sub-record whose header was read with
+    /// [`HprofReader::read_sub_record_header`] if `decode` is `true`.
+    /// Otherwise, the sub-record is skipped and `None` is returned.
+    pub(crate) fn read_sub_record_body(
+        &mut self,
+        tag: U1,
+        id: U8,
+        decode: bool,
+    ) -> Result<Option<HeapDumpTag>, Error> {
+        if !decode {
+            self.skip_sub_record_rest(tag, id)?;
+            return Ok(None);
+        }
+        self.read_sub_record_rest(tag, id).map(Some)
+    }
+
+    /// Reads the rest of a sub-record whose tag and first identifier `id` were read already.
+    fn read_sub_record_rest(&mut self, tag: U1, id: U8) -> Result<HeapDumpTag, Error> {
+        match tag {
+            0x01 => self.read_gc_root_jni_global(ObjectId(id)),
+            0x02 => self.read_gc_root_jni_local(ObjectId(id)),
+            0x03 => self.read_gc_root_java_frame(ObjectId(id)),
+            0x04 => self.read_gc_root_native_stack(ObjectId(id)),
+            0x05 => self.read_gc_root_sticky_class(ObjectId(id)),
+            0x06 => self.read_gc_root_thread_block(ObjectId(id)),
+            0x07 => self.read_gc_root_monitor_used(ObjectId(id)),
+            0x08 => self.read_gc_root_thread_obj(ObjectId(id)),
+            0xFF => self.read_gc_root_unknown(ObjectId(id)),
+            0x20 => self.read_gc_class_dump(ClassId(id)),
+            0x21 => self.read_gc_instance_dump(ObjectId(id)),
+            0x22 => self.read_gc_obj_array_dump(ObjectId(id)),
+            0x23 => self.read_gc_prim_array_dump(ObjectId(id)),
+            _ => Err(unknown_sub_record(tag)),
+        }
+    }
+
+    /// Skips the rest of a sub-record whose tag and first identifier `id` were read already.
+    fn skip_sub_record_rest(&mut self, tag: U1, id: U8) -> Result<(), Error> {
+        let id_size = I::size() as u64;
+        match tag {
+            0x01 => self.reader.skip(id_size)?,
+            0x02 | 0x03 | 0x08 => self.reader.skip(8)?,
+            0x04 | 0x06 => self.reader.skip(4)?,
+            0x05 | 0x07 | 0xFF => {}
+            0x20 => {
+                // class dumps are needed to decode instances later on
+                self.read_gc_class_dump(ClassId(id))?;
+            }
+            0x21 => {
+                self.reader.skip(id_size + 4)?;
+                let size = self.reader.read_u4()?;
+                self.reader.skip(size as u64)?;
+            }
+            0x22 => {
+                self.reader.skip(4)?;
+                let element_count = self.reader.read_u4()?;
+                self.reader.skip(id_size * (element_count as u64 + 1))?;
+            }
+            0x23 => {
+                self.reader.skip(4)?;
+                let element_count = self.reader.read_u4()?;
+                let element_type = self.reader.read_basic_type()?;
+                self.reader
+                    .skip(self.reader.type_size(element_type) * element_count as u64)?;
+            }
+            _ => return Err(unknown_sub_record(tag)),
+        }
+        Ok(())
+    }
+
+    /// Reads the rest of the heap dump and returns the index of all objects, including
+    /// classes, that the reader read with [`ReaderOptions::index_objects`]. Sub-records
+    /// are skipped without being decoded, so nothing is read twice if the reader is at the
+    /// end of the dump already. Objects in segments skipped by a visitor are not indexed.
+    ///
+    /// Fails if the option is not set. The index is only built once.
+    pub fn index_objects(&mut self) -> Result<ObjectIndex, Error> {
+        if self.object_index.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the reader does not index objects",
+            ));
+        }
+        loop {
+            if self.current_segment.is_none() {
+                let Some((tag, base)) = self.read_record_header()? else {
+                    break; // eof
+                };
+                if tag != 0x1C {
+                    self.read_record(tag, base)?;
+                    continue;
+                }
+                self.start_heap_dump_segment(base)?;
+            }
+            while self.has_sub_records()? {
+                let (_, tag, id) = self.read_sub_record_header()?;
+                self.skip_sub_record_rest(tag, id)?;
+            }
         }
-        Ok(Some(RecordTag::HprofHeapDumpSegment { base, sub_records }))
+        self.object_index.take().unwrap_or_default().build()
     }
 
-    fn read_heap_dump_end(&self, base: RecordBase) -> Result<Option<RecordTag>, Error> {
-        assert_eq!(0, base.size_remaining);
-        Ok(Some(RecordTag::HprofHeapDumpEnd))
+    /// Reads the object dump at `offset`, like an offset from an [`ObjectIndex`], and returns
+    /// to the current position afterwards. Fails with [`ErrorKind::InvalidInput`] if there is
+    /// no class, instance or array dump at `offset`.
+    pub fn read_object_at(&mut self, offset: U8) -> Result<HeapDumpTag, Error> {
+        let position = self.reader.buf_reader.stream_position()?;
+        // the object is not bounded by the current segment
+        let segment = self.current_segment.take();
+        self.reader.buf_reader.seek(SeekFrom::Start(offset))?;
+        let object = self.read_object();
+        self.current_segment = segment;
+        self.reader.buf_reader.seek(SeekFrom::Start(position))?;
+        object
     }
 
-    fn read_gc_class_dump(&mut self) -> Result<HeapDumpTag, Error> {
-        let class_object_id = self.read_identifier()?;
-        let stack_trace_serial_number = self.reader.read_u4()?;
-        let super_class_object_id = self.read_identifier()?;
-        let class_loader_object_id = self.read_identifier()?;
-        let signers_object_id = self.read_identifier()?;
-        let protection_domain_object_id = self.read_identifier()?;
-        let _ = self.read_identifier()?; // reserved
-        let _ = self.read_identifier()?; // reserved
+    fn read_object(&mut self) -> Result<HeapDumpTag, Error> {
+        let tag = self.reader.read_u1()?;
+        if !(0x20..=0x23).contains(&tag) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the offset is not the offset of an object",
+            ));
+        }
+        let id = self.reader.read_identifier()?;
+        self.read_sub_record_rest(tag, id)
+    }
+
+    fn read_heap_dump_end(&self, base: RecordBase) -> Result<RecordTag, Error> {
+        if base.size_remaining != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "HPROF_HEAP_DUMP_END record with a body",
+            ));
+        }
+        Ok(RecordTag::HprofHeapDumpEnd)
+    }
+
+    fn read_gc_class_dump(&mut self, class_object_id: ClassId) -> Result<HeapDumpTag, Error> {
+        let stack_trace_serial_number = TraceSerial(self.reader.read_u4()?);
+        let super_class_object_id = ClassId(self.reader.read_identifier()?);
+        let class_loader_object_id = ObjectId(self.reader.read_identifier()?);
+        let signers_object_id = ObjectId(self.reader.read_identifier()?);
+        let protection_domain_object_id = ObjectId(self.reader.read_identifier()?);
+        let _ = self.reader.read_identifier()?; // reserved
+        let _ = self.reader.read_identifier()?; // reserved
         let instance_size = self.reader.read_u4()?;
         let constant_pool_size = self.reader.read_u2()?;
-        assert_eq!(
-            constant_pool_size, 0,
-            "constant pool dumping is not supported"
-        );
+        if constant_pool_size != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "constant pool dumping is not supported",
+            ));
+        }
         let static_fields_count = self.reader.read_u2()?;
         let static_fields = self.read_fields(static_fields_count, true)?;
         let instance_field_count = self.reader.read_u2()?;
         let instance_fields = self.read_fields(instance_field_count, false)?;
-        // todo deal with lifetime of class_dump properly
-        let class_dump = ClassInfo {
+        let class_dump = Arc::new(ClassInfo {
             class_object_id,
             stack_trace_serial_number,
             super_class_object_id,
@@ -192,57 +944,38 @@ impl<T: Read + Seek> HprofReader<T> {
             signers_object_id,
             protection_domain_object_id,
             instance_size,
-            static_fields: static_fields.clone(),
-            instance_fields: instance_fields.clone(),
-        };
-        self.class_cache.insert(class_object_id, class_dump.clone());
+            static_fields,
+            instance_fields,
+        });
+        self.class_cache.insert(class_object_id.0, &class_dump)?;
         Ok(HeapDumpTag::HprofGcClassDump(class_dump))
     }
 
     fn read_fields(&mut self, field_count: U2, with_value: bool) -> Result<Vec<FieldInfo>, Error> {
         (0..field_count)
             .map(|_| {
-                let name_id = self.read_identifier()?;
-                let type_tag = self.reader.read_u1()?;
+                let name_id = StringId(self.reader.read_identifier()?);
+                let field_type = self.reader.read_basic_type()?;
                 let value = if with_value {
-                    Some(self.read_value(type_tag)?)
+                    Some(self.reader.read_value(field_type)?)
                 } else {
                     None
                 };
                 Ok(FieldInfo {
                     name_id,
-                    type_tag,
+                    field_type,
                     value,
                 })
             })
             .collect()
     }
 
-    fn read_value(&mut self, type_tag: U1) -> Result<Value, Error> {
-        let v = match type_tag {
-            0x01 => Value::Array {
-                object_id: self.read_identifier()?,
-            },
-            0x02 => Value::Object {
-                object_id: self.read_identifier()?,
-            },
-            0x04 => Value::Boolean(self.reader.read_u1()? != 0),
-            0x05 => Value::Char(self.reader.read_u2()?),
-            0x06 => Value::Float(f32::from_bits(self.reader.read_u4()?)),
-            0x07 => Value::Double(f64::from_bits(self.reader.read_u8()?)),
-            0x08 => Value::Byte(self.reader.read_u1()? as i8),
-            0x09 => Value::Short(self.reader.read_u2()? as i16),
-            0x0A => Value::Int(self.reader.read_u4()? as i32),
-            0x0B => Value::Long(self.reader.read_u8()? as i64),
-            _ => panic!("unsupported type tag {type_tag}"),
-        };
-        Ok(v)
-    }
-
-    fn read_gc_root_thread_obj(&mut self) -> Result<HeapDumpTag, Error> {
-        let thread_object_id = self.read_identifier()?;
-        let thread_sequence_number = self.reader.read_u4()?;
-        let stack_trace_sequence_number = self.reader.read_u4()?;
+    fn read_gc_root_thread_obj(
+        &mut self,
+        thread_object_id: ObjectId,
+    ) -> Result<HeapDumpTag, Error> {
+        let thread_sequence_number = ThreadSerial(self.reader.read_u4()?);
+        let stack_trace_sequence_number = TraceSerial(self.reader.read_u4()?);
         Ok(HeapDumpTag::HprofGcRootThreadObj {
             thread_object_id,
             thread_sequence_number,
@@ -250,9 +983,8 @@ impl<T: Read + Seek> HprofReader<T> {
         })
     }
 
-    fn read_gc_root_java_frame(&mut self) -> Result<HeapDumpTag, Error> {
-        let object_id = self.read_identifier()?;
-        let thread_serial_number = self.reader.read_u4()?;
+    fn read_gc_root_java_frame(&mut self, object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        let thread_serial_number = ThreadSerial(self.reader.read_u4()?);
         let frame_number = self.reader.read_u4()?;
         Ok(HeapDumpTag::HprofGcRootJavaFrame {
             object_id,
@@ -261,9 +993,8 @@ impl<T: Read + Seek> HprofReader<T> {
         })
     }
 
-    fn read_gc_root_jni_local(&mut self) -> Result<HeapDumpTag, Error> {
-        let object_id = self.read_identifier()?;
-        let thread_serial_number = self.reader.read_u4()?;
+    fn read_gc_root_jni_local(&mut self, object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        let thread_serial_number = ThreadSerial(self.reader.read_u4()?);
         let frame_number = self.reader.read_u4()?;
         Ok(HeapDumpTag::HprofGcRootJniLocal {
             object_id,
@@ -272,42 +1003,189 @@ impl<T: Read + Seek> HprofReader<T> {
         })
     }
 
-    fn read_gc_root_jni_global(&mut self) -> Result<HeapDumpTag, Error> {
-        let object_id = self.read_identifier()?;
-        let jni_global_ref_id = self.read_identifier()?;
+    fn read_gc_root_jni_global(&mut self, object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        let jni_global_ref_id = ObjectId(self.reader.read_identifier()?);
         Ok(HeapDumpTag::HprofGcRootJniGlobal {
             object_id,
             jni_global_ref_id,
         })
     }
 
-    fn read_gc_root_sticky_class(&mut self) -> Result<HeapDumpTag, Error> {
-        let object_id = self.read_identifier()?;
+    fn read_gc_root_native_stack(&mut self, object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        let thread_serial_number = ThreadSerial(self.reader.read_u4()?);
+        Ok(HeapDumpTag::HprofGcRootNativeStack {
+            object_id,
+            thread_serial_number,
+        })
+    }
+
+    fn read_gc_root_sticky_class(&mut self, object_id: ObjectId) -> Result<HeapDumpTag, Error> {
         Ok(HeapDumpTag::HprofGcRootStickyClass { object_id })
     }
 
-    pub fn name(&self, id: U8) -> Option<&String> {
-        self.name_cache.get(&id)
+    fn read_gc_root_thread_block(&mut self, object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        let thread_serial_number = ThreadSerial(self.reader.read_u4()?);
+        Ok(HeapDumpTag::HprofGcRootThreadBlock {
+            object_id,
+            thread_serial_number,
+        })
+    }
+
+    fn read_gc_root_monitor_used(&mut self, object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        Ok(HeapDumpTag::HprofGcRootMonitorUsed { object_id })
+    }
+
+    fn read_gc_root_unknown(&mut self, object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        Ok(HeapDumpTag::HprofGcRootUnknown { object_id })
+    }
+
+    pub fn name(&self, id: StringId) -> Result<Option<Cow<'_, str>>, Error> {
+        self.name_cache.get(id.0)
+    }
+
+    /// Returns the name id of the `HPROF_LOAD_CLASS` record of a class, if it was read already.
+    pub fn class_name_id(&self, class_object_id: ClassId) -> Option<StringId> {
+        self.class_names.get(&class_object_id).copied()
+    }
+
+    /// Returns the name of a class in JVM form, like `java/lang/String`. See
+    /// [`crate::names`] to format it.
+    pub fn class_name(&self, class_object_id: ClassId) -> Result<Option<Cow<'_, str>>, Error> {
+        match self.class_name_id(class_object_id) {
+            Some(name_id) => self.name(name_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the stack trace with the given serial number, if it was read already.
+    pub fn stack_trace(&self, serial_number: TraceSerial) -> Result<Option<StackTrace>, Error> {
+        let Some((thread_serial_number, frame_ids)) = self.stack_traces.traces.get(&serial_number)
+        else {
+            return Ok(None);
+        };
+        let frames = frame_ids
+            .iter()
+            .map(|&frame_id| self.stack_frame(frame_id))
+            .collect::<Result<_, Error>>()?;
+        Ok(Some(StackTrace {
+            serial_number,
+            thread_serial_number: *thread_serial_number,
+            frames,
+        }))
+    }
+
+    /// Returns all stack traces read so far, ordered by their serial number.
+    pub fn stack_traces(&self) -> Result<Vec<StackTrace>, Error> {
+        let mut serial_numbers: Vec<_> = self.stack_traces.traces.keys().copied().collect();
+        serial_numbers.sort();
+        serial_numbers
+            .into_iter()
+            .filter_map(|serial_number| self.stack_trace(serial_number).transpose())
+            .collect()
     }
 
-    fn read_gc_instance_dump(&mut self) -> Result<HeapDumpTag, Error> {
-        let object_id = self.read_identifier()?;
-        let stack_trace_serial_number = self.reader.read_u4()?;
-        let class_object_id = self.read_identifier()?;
-        let until = self.reader.read_u4()? as u64 + self.reader.buf_reader.stream_position()?;
+    /// Resolves the names of a frame. Frames without an `HPROF_FRAME` record are returned
+    /// with no names and an unknown line number.
+    fn stack_frame(&self, frame_id: FrameId) -> Result<StackFrame, Error> {
+        let Some(frame) = self.stack_traces.frames.get(&frame_id) else {
+            return Ok(StackFrame {
+                frame_id,
+                class_name: None,
+                method_name: None,
+                method_signature: None,
+                source_file: None,
+                line_number: LineNumber::Unknown,
+            });
+        };
+        let name =
+            |id| -> Result<Option<String>, Error> { Ok(self.name(id)?.map(Cow::into_owned)) };
+        let class_name = match self
+            .stack_traces
+            .class_serials
+            .get(&frame.class_serial_number)
+        {
+            Some(&class_object_id) => self.class_name(class_object_id)?.map(Cow::into_owned),
+            None => None,
+        };
+        Ok(StackFrame {
+            frame_id,
+            class_name,
+            method_name: name(frame.method_name_id)?,
+            method_signature: name(frame.method_signature_id)?,
+            source_file: name(frame.source_file_name_id)?,
+            line_number: LineNumber::from(frame.line_number),
+        })
+    }
 
-        let mut class = self.get_class_by_id(class_object_id)?.clone();
-        let mut field_iter = class.instance_fields.iter();
-        let mut values = Vec::new();
-        while self.reader.buf_reader.stream_position()? < until {
-            let mut field_opt = field_iter.next();
-            while field_opt.is_none() {
-                class = self.get_class_by_id(class.super_class_object_id)?.clone();
-                field_iter = class.instance_fields.iter();
-                field_opt = field_iter.next()
+    /// Returns the flattened instance field layout of a class, which is cached, see
+    /// [`ReaderOptions::class_storage`].
+    pub fn class_layout(&mut self, class_object_id: ClassId) -> Result<Arc<ClassLayout>, Error> {
+        if let Some(layout) = self.layouts.get(class_object_id) {
+            return Ok(layout);
+        }
+        let missing_class = || Error::new(ErrorKind::InvalidData, "missing class dump");
+        // the class and its superclasses up to the first one with a cached layout
+        let mut chain = vec![class_object_id];
+        let mut visited = HashSet::from([class_object_id]);
+        let mut super_layout = None;
+        loop {
+            let class_id = chain[chain.len() - 1];
+            let super_class_id = self
+                .class_cache
+                .get(class_id.0)?
+                .ok_or_else(missing_class)?
+                .super_class_object_id;
+            if super_class_id.is_null() {
+                break;
             }
-            values.push(self.read_value(field_opt.unwrap().type_tag)?)
+            if let Some(layout) = self.layouts.get(super_class_id) {
+                super_layout = Some(layout);
+                break;
+            }
+            if !visited.insert(super_class_id) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "cyclic superclass chain",
+                ));
+            }
+            chain.push(super_class_id);
         }
+        // the fields of each superclass are shared with the layouts of its subclasses
+        for class_id in chain.into_iter().rev() {
+            let class = self
+                .class_cache
+                .get(class_id.0)?
+                .ok_or_else(missing_class)?;
+            let layout = Arc::new(ClassLayout::compute(
+                &class,
+                super_layout.as_deref(),
+                self.name_cache.as_ref(),
+                self.identifier_size(),
+            )?);
+            self.layouts.insert(layout.clone());
+            super_layout = Some(layout);
+        }
+        Ok(super_layout.expect("the chain contains the class"))
+    }
+
+    /// Pairs the field values of an instance dump with the fields of its class. The values
+    /// must have been read with [`FieldDecoding::All`].
+    pub fn resolve_instance(
+        &mut self,
+        object_id: ObjectId,
+        class_object_id: ClassId,
+        instance_field_values: Vec<Value>,
+    ) -> Result<Instance, Error> {
+        let layout = self.class_layout(class_object_id)?;
+        Instance::new(object_id, layout, instance_field_values)
+    }
+
+    fn read_gc_instance_dump(&mut self, object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        let stack_trace_serial_number = TraceSerial(self.reader.read_u4()?);
+        let class_object_id = ClassId(self.reader.read_identifier()?);
+        let size = self.reader.read_u4()?;
+        let mut values = vec![];
+        self.read_instance_fields(class_object_id, size, &mut values)?;
         Ok(HeapDumpTag::HprofGcInstanceDump {
             object_id,
             stack_trace_serial_number,
@@ -316,20 +1194,47 @@ impl<T: Read + Seek> HprofReader<T> {
         })
     }
 
-    fn get_class_by_id(&self, object_id: U8) -> Result<&ClassInfo, Error> {
-        self.class_cache
-            .get(&object_id)
-            .ok_or(Error::other("missing class"))
+    /// Reads the `size` bytes of field data of an instance and decodes them into `values`,
+    /// as selected by [`ReaderOptions::instance_fields`].
+    fn read_instance_fields(
+        &mut self,
+        class_object_id: ClassId,
+        size: U4,
+        values: &mut Vec<Value>,
+    ) -> Result<(), Error> {
+        values.clear();
+        let decoding = self.options.instance_fields;
+        if decoding == FieldDecoding::None {
+            return self.reader.skip(size as u64);
+        }
+        let layout = self.class_layout(class_object_id)?;
+        if layout.size != size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "instance size does not match the class layout",
+            ));
+        }
+        let data = &mut self.field_buffer;
+        data.resize(size as usize, 0);
+        self.reader.buf_reader.read_exact(data)?;
+        values.extend(
+            layout
+                .fields
+                .iter()
+                .filter(|f| decoding == FieldDecoding::All || !f.field_type.is_primitive())
+                .map(|f| decode_value::<I>(&data[f.offset as usize..], f.field_type)),
+        );
+        Ok(())
     }
 
-    fn read_gc_obj_array_dump(&mut self) -> Result<HeapDumpTag, Error> {
-        let array_object_id = self.read_identifier()?;
-        let stack_trace_serial_number = self.reader.read_u4()?;
+    fn read_gc_obj_array_dump(&mut self, array_object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        let stack_trace_serial_number = TraceSerial(self.reader.read_u4()?);
         let element_count = self.reader.read_u4()?;
-        let array_class_id = self.read_identifier()?;
+        let array_class_id = ClassId(self.reader.read_identifier()?);
+        self.check_remaining(element_count, I::size() as u64)?;
         let mut elements = Vec::with_capacity(element_count as usize);
         for i in 0..(element_count as usize) {
-            elements.insert(i, self.read_identifier()?);
+            elements.insert(i, ObjectId(self.reader.read_identifier()?));
         }
         Ok(HeapDumpTag::HprofGcObjArrayDump {
             array_object_id,
@@ -339,24 +1244,65 @@ impl<T: Read + Seek> HprofReader<T> {
         })
     }
 
-    fn read_gc_prim_array_dump(&mut self) -> Result<HeapDumpTag, Error> {
-        let array_object_id = self.read_identifier()?;
-        let stack_trace_serial_number = self.reader.read_u4()?;
+    fn read_gc_prim_array_dump(&mut self, array_object_id: ObjectId) -> Result<HeapDumpTag, Error> {
+        let stack_trace_serial_number = TraceSerial(self.reader.read_u4()?);
         let element_count = self.reader.read_u4()?;
-        let type_tag = self.reader.read_u1()?;
-        let mut elements = Vec::with_capacity(element_count as usize);
-        for i in 0..(element_count as usize) {
-            elements.insert(i, self.read_value(type_tag)?);
-        }
+        let element_type = self.reader.read_basic_type()?;
+        let elements = if self.options.skip_primitive_array_bodies {
+            let size = self.reader.type_size(element_type) * element_count as u64;
+            self.reader.skip(size)?;
+            None
+        } else {
+            let mut elements = PrimitiveArray::Byte(vec![]);
+            self.read_primitive_array(element_type, element_count, &mut elements)?;
+            Some(elements)
+        };
         Ok(HprofGcPrimArrayDump {
             array_object_id,
             stack_trace_serial_number,
+            element_type,
+            element_count,
             elements,
         })
     }
+
+    /// Reads the elements of a primitive array into `elements`, reusing its allocation if
+    /// it has the same element type.
+    fn read_primitive_array(
+        &mut self,
+        element_type: BasicType,
+        element_count: U4,
+        elements: &mut PrimitiveArray,
+    ) -> Result<(), Error> {
+        if element_type == BasicType::Object {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "primitive array with object elements",
+            ));
+        }
+        let element_size = self.reader.type_size(element_type);
+        self.check_remaining(element_count, element_size)?;
+        let bytes = &mut self.field_buffer;
+        bytes.resize(element_count as usize * element_size as usize, 0);
+        self.reader.buf_reader.read_exact(bytes)?;
+        match element_type {
+            BasicType::Boolean => {
+                replace_elements!(elements, Boolean, bytes.iter().map(|&b| b != 0))
+            }
+            BasicType::Char => replace_elements!(elements, Char, from_be_chunks!(bytes, u16)),
+            BasicType::Float => replace_elements!(elements, Float, from_be_chunks!(bytes, f32)),
+            BasicType::Double => replace_elements!(elements, Double, from_be_chunks!(bytes, f64)),
+            BasicType::Byte => replace_elements!(elements, Byte, bytes.iter().map(|&b| b as i8)),
+            BasicType::Short => replace_elements!(elements, Short, from_be_chunks!(bytes, i16)),
+            BasicType::Int => replace_elements!(elements, Int, from_be_chunks!(bytes, i32)),
+            BasicType::Long => replace_elements!(elements, Long, from_be_chunks!(bytes, i64)),
+            BasicType::Object => unreachable!(),
+        }
+        Ok(())
+    }
 }
 
-impl<R: Read + Seek> Iterator for HprofReader<R> {
+impl<R: Read + Seek, I: Identifier> Iterator for HprofReader<R, I> {
     type Item = Result<RecordTag, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -364,27 +1310,133 @@ impl<R: Read + Seek> Iterator for HprofReader<R> {
     }
 }
 
-impl<T: Read + Seek> InternalHprofReader<T> {
-    fn new(b: T) -> Self {
-        Self { buf_reader: b }
+impl<T: Read + Seek> Input<T> {
+    fn new(mut buf_reader: T) -> Result<Input<T>, Error> {
+        let start = buf_reader.stream_position()?;
+        let mut magic = [0u8; 2];
+        let is_gzip = buf_reader.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+        buf_reader.seek(SeekFrom::Start(start))?;
+        let input = if is_gzip {
+            Input::Gzip(Box::new(GzipReader::new(buf_reader)?))
+        } else {
+            Input::Plain(buf_reader)
+        };
+        Ok(input)
     }
 
-    fn read_hprof(mut self) -> Result<HprofReader<T>, Error> {
-        let header = "JAVA PROFILE 1.0.2\0";
-        let mut buf = [0u8; 19];
-        self.buf_reader.read_exact(&mut buf)?;
-        if buf.to_vec() != Vec::from(header) {
-            return Err(Error::from(ErrorKind::InvalidInput));
+    /// The length of the decompressed input, if it is known.
+    fn len(&mut self) -> Result<Option<U8>, Error> {
+        match self {
+            Input::Plain(input) => {
+                let position = input.stream_position()?;
+                let len = input.seek(SeekFrom::End(0))?;
+                input.seek(SeekFrom::Start(position))?;
+                Ok(Some(len))
+            }
+            Input::Gzip(_) => Ok(None),
         }
-        let identifier_size = self.read_u4()?;
-        let timestamp = self.read_u8()?;
-        Ok(HprofReader {
-            identifier_size,
-            timestamp,
-            reader: self,
-            name_cache: HashMap::new(),
-            class_cache: HashMap::new(),
-        })
+    }
+}
+
+impl<T: Read + Seek> Read for Input<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            Input::Plain(input) => input.read(buf),
+            Input::Gzip(input) => input.read(buf),
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        match self {
+            Input::Plain(input) => input.read_exact(buf),
+            Input::Gzip(input) => input.read_exact(buf),
+        }
+    }
+}
+
+impl<T: Read + Seek> Seek for Input<T> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        match self {
+            Input::Plain(input) => input.seek(pos),
+            Input::Gzip(input) => input.seek(pos),
+        }
+    }
+
+    fn stream_position(&mut self) -> Result<u64, Error> {
+        match self {
+            Input::Plain(input) => input.stream_position(),
+            Input::Gzip(input) => input.stream_position(),
+        }
+    }
+
+    fn seek_relative(&mut self, offset: i64) -> Result<(), Error> {
+        match self {
+            Input::Plain(input) => input.seek_relative(offset),
+            Input::Gzip(input) => input.seek_relative(offset),
+        }
+    }
+}
+
+pub(crate) fn unknown_sub_record(tag: U1) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("unknown sub-record tag {tag:#x}"),
+    )
+}
+
+/// Reads the file header and returns the identifier size and the timestamp.
+fn read_header(input: &mut impl Read) -> Result<(U4, U8), Error> {
+    let header = "JAVA PROFILE 1.0.2\0";
+    let mut buf = [0u8; 19];
+    input.read_exact(&mut buf)?;
+    if buf.to_vec() != Vec::from(header) {
+        return Err(Error::from(ErrorKind::InvalidInput));
+    }
+    let mut buf = [0u8; 12];
+    input.read_exact(&mut buf)?;
+    let identifier_size = U4::from_be_bytes(buf[..4].try_into().unwrap());
+    if !matches!(identifier_size, 4 | 8) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported identifier size {identifier_size}"),
+        ));
+    }
+    let timestamp = U8::from_be_bytes(buf[4..].try_into().unwrap());
+    Ok((identifier_size, timestamp))
+}
+
+impl<T: Read + Seek, I: Identifier> InternalHprofReader<T, I> {
+    fn read_identifier(&mut self) -> Result<U8, Error> {
+        I::read(&mut self.buf_reader)
+    }
+
+    fn read_value(&mut self, basic_type: BasicType) -> Result<Value, Error> {
+        let v = match basic_type {
+            BasicType::Object => Value::Object {
+                object_id: ObjectId(self.read_identifier()?),
+            },
+            BasicType::Boolean => Value::Boolean(self.read_u1()? != 0),
+            BasicType::Char => Value::Char(self.read_u2()?),
+            BasicType::Float => Value::Float(f32::from_bits(self.read_u4()?)),
+            BasicType::Double => Value::Double(f64::from_bits(self.read_u8()?)),
+            BasicType::Byte => Value::Byte(self.read_u1()? as i8),
+            BasicType::Short => Value::Short(self.read_u2()? as i16),
+            BasicType::Int => Value::Int(self.read_u4()? as i32),
+            BasicType::Long => Value::Long(self.read_u8()? as i64),
+        };
+        Ok(v)
+    }
+
+    fn read_basic_type(&mut self) -> Result<BasicType, Error> {
+        BasicType::try_from(self.read_u1()?)
+    }
+
+    fn type_size(&self, basic_type: BasicType) -> u64 {
+        basic_type.size(I::size() as U4) as u64
+    }
+
+    fn skip(&mut self, n: u64) -> Result<(), Error> {
+        self.buf_reader.seek_relative(n as i64)
     }
 
     define_read_ux!(read_u1, U1, 1);
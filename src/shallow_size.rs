@@ -0,0 +1,131 @@
+use crate::hprof_model::{BasicType, HeapDumpTag, Identifier, U4, U8};
+use crate::layout::ClassLayout;
+use crate::reader::HprofReader;
+use std::io::{Error, Read, Seek};
+
+/// The layout parameters of the JVM that wrote a heap dump, which decide the shallow size
+/// of its objects on the heap.
+///
+/// The dump only records the identifier size, which is 8 on 64-bit JVMs with and without
+/// compressed references, so [`JvmLayout::from_identifier_size`] assumes compressed
+/// references like jmap and Eclipse MAT do. Use [`JvmLayout::COMPRESSED_CLASS_POINTERS`] for
+/// heaps of 32 GB and more, or set the fields for other flags.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct JvmLayout {
+    /// The size of the header of instances, the mark word and the class pointer.
+    pub object_header: U4,
+    /// The size of the header of arrays, including the length.
+    pub array_header: U4,
+    /// The size of references in fields and object arrays.
+    pub reference_size: U4,
+    /// Objects are padded to a multiple of this size.
+    pub alignment: U4,
+}
+
+impl JvmLayout {
+    /// A 64-bit JVM with compressed references and class pointers, the default for heaps
+    /// smaller than 32 GB.
+    pub const COMPRESSED: JvmLayout = JvmLayout {
+        object_header: 12,
+        array_header: 16,
+        reference_size: 4,
+        alignment: 8,
+    };
+
+    /// A 64-bit JVM with compressed class pointers but without compressed references, the
+    /// default for heaps of 32 GB and more since JDK 15.
+    pub const COMPRESSED_CLASS_POINTERS: JvmLayout = JvmLayout {
+        object_header: 12,
+        array_header: 16,
+        reference_size: 8,
+        alignment: 8,
+    };
+
+    /// A 64-bit JVM without compressed references and class pointers, the default for heaps
+    /// of 32 GB and more before JDK 15.
+    pub const UNCOMPRESSED: JvmLayout = JvmLayout {
+        object_header: 16,
+        array_header: 24,
+        reference_size: 8,
+        alignment: 8,
+    };
+
+    /// A 32-bit JVM.
+    pub const BITS_32: JvmLayout = JvmLayout {
+        object_header: 8,
+        array_header: 12,
+        reference_size: 4,
+        alignment: 8,
+    };
+
+    /// Returns [`JvmLayout::BITS_32`] for an identifier size of 4, and
+    /// [`JvmLayout::COMPRESSED`] otherwise.
+    pub fn from_identifier_size(identifier_size: U4) -> JvmLayout {
+        match identifier_size {
+            4 => JvmLayout::BITS_32,
+            _ => JvmLayout::COMPRESSED,
+        }
+    }
+
+    /// The size of a field or an array element of the given type on the heap.
+    pub fn value_size(&self, basic_type: BasicType) -> U4 {
+        basic_type.size(self.reference_size)
+    }
+
+    /// The size of the instance fields of a class on the heap, without the header.
+    pub fn fields_size(&self, class_layout: &ClassLayout) -> U8 {
+        class_layout
+            .fields
+            .iter()
+            .map(|f| self.value_size(f.field_type) as U8)
+            .sum()
+    }
+
+    /// The shallow size of an instance whose fields take up `fields_size` bytes.
+    ///
+    /// Fields are assumed to be packed without gaps, so this can be smaller than the size on
+    /// the heap when the JVM leaves gaps between fields to align them.
+    pub fn instance_size(&self, fields_size: U8) -> U8 {
+        self.align(self.object_header as U8 + fields_size)
+    }
+
+    /// The shallow size of an array with the given element type and length.
+    pub fn array_size(&self, element_type: BasicType, length: U8) -> U8 {
+        let element_size = self.value_size(element_type) as U8;
+        // elements of 8 bytes start at an offset that is a multiple of 8
+        let base = self.array_header as U8;
+        let base = base.next_multiple_of(element_size.min(8));
+        self.align(base + length * element_size)
+    }
+
+    /// Returns the shallow size of the object of an instance or array dump, or `None` for
+    /// other sub-records. The layout of instances is looked up in `reader`.
+    pub fn shallow_size<T: Read + Seek, I: Identifier>(
+        &self,
+        reader: &mut HprofReader<T, I>,
+        sub_record: &HeapDumpTag,
+    ) -> Result<Option<U8>, Error> {
+        let size = match sub_record {
+            HeapDumpTag::HprofGcInstanceDump {
+                class_object_id, ..
+            } => {
+                let class_layout = reader.class_layout(*class_object_id)?;
+                self.instance_size(self.fields_size(&class_layout))
+            }
+            HeapDumpTag::HprofGcObjArrayDump { elements, .. } => {
+                self.array_size(BasicType::Object, elements.len() as U8)
+            }
+            HeapDumpTag::HprofGcPrimArrayDump {
+                element_type,
+                element_count,
+                ..
+            } => self.array_size(*element_type, *element_count as U8),
+            _ => return Ok(None),
+        };
+        Ok(Some(size))
+    }
+
+    fn align(&self, size: U8) -> U8 {
+        size.next_multiple_of(self.alignment.max(1) as U8)
+    }
+}
@@ -0,0 +1,102 @@
+use crate::hprof_model::{
+    ClassId, ClassSerial, FrameId, StringId, ThreadSerial, TraceSerial, I4, U4,
+};
+use crate::names;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// The line number of a `HPROF_FRAME` record.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineNumber {
+    Line(U4),
+    /// No line number information is available (`0`).
+    None,
+    /// `-1`, and any other negative number without a meaning.
+    Unknown,
+    /// The method is compiled (`-2`).
+    Compiled,
+    /// The method is native (`-3`).
+    Native,
+}
+
+impl From<I4> for LineNumber {
+    fn from(line_number: I4) -> Self {
+        match line_number {
+            1.. => LineNumber::Line(line_number as U4),
+            0 => LineNumber::None,
+            -2 => LineNumber::Compiled,
+            -3 => LineNumber::Native,
+            _ => LineNumber::Unknown,
+        }
+    }
+}
+
+/// A stack frame whose names are resolved. Names are `None` if their records are missing.
+#[derive(Clone, Debug)]
+pub struct StackFrame {
+    pub frame_id: FrameId,
+    /// The name of the class in JVM form, like `java/lang/Thread`.
+    pub class_name: Option<String>,
+    pub method_name: Option<String>,
+    /// The method descriptor, like `(Ljava/lang/String;)V`.
+    pub method_signature: Option<String>,
+    pub source_file: Option<String>,
+    pub line_number: LineNumber,
+}
+
+/// Prints the frame like the JVM does, like `java.lang.Thread.run(Thread.java:829)`.
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let class_name = self.class_name.as_deref().map(names::binary_name);
+        write!(
+            f,
+            "{}.{}(",
+            class_name.as_deref().unwrap_or("<unknown class>"),
+            self.method_name.as_deref().unwrap_or("<unknown method>")
+        )?;
+        match (self.line_number, &self.source_file) {
+            (LineNumber::Native, _) => write!(f, "Native Method")?,
+            (LineNumber::Compiled, _) => write!(f, "Compiled Method")?,
+            (LineNumber::Line(line), Some(file)) => write!(f, "{file}:{line}")?,
+            (_, Some(file)) => write!(f, "{file}")?,
+            (_, None) => write!(f, "Unknown Source")?,
+        }
+        write!(f, ")")
+    }
+}
+
+/// A stack trace of a `HPROF_TRACE` record with resolved frames, innermost frame first.
+#[derive(Clone, Debug)]
+pub struct StackTrace {
+    pub serial_number: TraceSerial,
+    pub thread_serial_number: ThreadSerial,
+    pub frames: Vec<StackFrame>,
+}
+
+/// Prints one `\tat` line per frame, like the JVM prints a stack trace after the exception.
+impl Display for StackTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for frame in &self.frames {
+            writeln!(f, "\tat {frame}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A `HPROF_FRAME` record as it is kept by the reader.
+#[derive(Clone, Copy)]
+pub(crate) struct FrameInfo {
+    pub method_name_id: StringId,
+    pub method_signature_id: StringId,
+    pub source_file_name_id: StringId,
+    pub class_serial_number: ClassSerial,
+    pub line_number: I4,
+}
+
+/// The frames and traces read so far, and the classes by their serial number.
+#[derive(Default)]
+pub(crate) struct StackTraceTable {
+    pub frames: HashMap<FrameId, FrameInfo>,
+    pub traces: HashMap<TraceSerial, (ThreadSerial, Vec<FrameId>)>,
+    pub class_serials: HashMap<ClassSerial, ClassId>,
+}
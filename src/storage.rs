@@ -0,0 +1,411 @@
+use crate::hprof_model::{
+    BasicType, ClassId, ClassInfo, FieldInfo, ObjectId, StringId, TraceSerial, Value,
+};
+use crate::hprof_model::{U1, U2, U4, U8};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Selects how [`crate::reader::HprofReader`] stores the strings and classes it needs
+/// to keep while reading a heap dump.
+#[derive(Clone, Default, Debug)]
+pub enum TableStorage {
+    /// A hash map that shares the values with the records returned by the reader.
+    #[default]
+    HashMap,
+    /// All entries are encoded back to back in one buffer, with a hash map as index.
+    Arena,
+    /// All entries are encoded back to back in one buffer, with sorted vectors as index.
+    /// Uses less memory than [`TableStorage::Arena`], but lookups are slower.
+    SortedVec,
+    /// All entries are encoded into a temporary file in the given directory.
+    /// Only the index is kept in memory.
+    Spill { directory: PathBuf },
+}
+
+impl TableStorage {
+    pub(crate) fn create<V: Storable + Send + Sync + ?Sized + 'static>(
+        &self,
+    ) -> Result<Box<dyn Table<V>>, Error> {
+        let table: Box<dyn Table<V>> = match self {
+            TableStorage::HashMap => Box::new(HashTable::<V>::default()),
+            TableStorage::Arena => Box::new(ArenaTable::default()),
+            TableStorage::SortedVec => Box::new(SortedVecTable::default()),
+            TableStorage::Spill { directory } => Box::new(SpillTable::new(directory)?),
+        };
+        Ok(table)
+    }
+}
+
+/// Stores values by their id.
+pub trait Table<V: Storable + ?Sized>: Send {
+    /// Inserts or replaces the value with the given id.
+    ///
+    /// In-memory tables share the value, all others store an encoded copy.
+    fn insert(&mut self, id: U8, value: &Arc<V>) -> Result<(), Error>;
+
+    fn get(&self, id: U8) -> Result<Option<Cow<'_, V>>, Error>;
+
+    /// Returns the ids of all values, in no particular order.
+    fn ids(&self) -> Vec<U8>;
+}
+
+/// A value that can be stored in a [`Table`] in encoded form.
+pub trait Storable: ToOwned {
+    fn encode(&self, out: &mut Vec<u8>);
+
+    fn decode(bytes: &[u8]) -> Result<Cow<'_, Self>, Error>;
+}
+
+impl Storable for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Cow<'_, Self>, Error> {
+        std::str::from_utf8(bytes)
+            .map(Cow::Borrowed)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+impl Storable for ClassInfo {
+    fn encode(&self, out: &mut Vec<u8>) {
+        for id in [
+            self.class_object_id.0,
+            self.super_class_object_id.0,
+            self.class_loader_object_id.0,
+            self.signers_object_id.0,
+            self.protection_domain_object_id.0,
+        ] {
+            out.extend_from_slice(&id.to_be_bytes());
+        }
+        out.extend_from_slice(&self.stack_trace_serial_number.0.to_be_bytes());
+        out.extend_from_slice(&self.instance_size.to_be_bytes());
+        for fields in [&self.static_fields, &self.instance_fields] {
+            out.extend_from_slice(&(fields.len() as U2).to_be_bytes());
+            for field in fields {
+                out.extend_from_slice(&field.name_id.0.to_be_bytes());
+                out.push(field.field_type.tag());
+                match &field.value {
+                    None => out.push(0),
+                    Some(value) => {
+                        out.push(1);
+                        encode_value(value, out);
+                    }
+                }
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Cow<'_, Self>, Error> {
+        let mut bytes = bytes;
+        let class_object_id = ClassId(read_u8(&mut bytes)?);
+        let super_class_object_id = ClassId(read_u8(&mut bytes)?);
+        let class_loader_object_id = ObjectId(read_u8(&mut bytes)?);
+        let signers_object_id = ObjectId(read_u8(&mut bytes)?);
+        let protection_domain_object_id = ObjectId(read_u8(&mut bytes)?);
+        let stack_trace_serial_number = TraceSerial(read_u4(&mut bytes)?);
+        let instance_size = read_u4(&mut bytes)?;
+        let static_fields = decode_fields(&mut bytes)?;
+        let instance_fields = decode_fields(&mut bytes)?;
+        Ok(Cow::Owned(ClassInfo {
+            class_object_id,
+            stack_trace_serial_number,
+            super_class_object_id,
+            class_loader_object_id,
+            signers_object_id,
+            protection_domain_object_id,
+            instance_size,
+            static_fields,
+            instance_fields,
+        }))
+    }
+}
+
+fn encode_value(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Object { object_id } => out.extend_from_slice(&object_id.0.to_be_bytes()),
+        Value::Boolean(v) => out.push(*v as U1),
+        Value::Byte(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::Char(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::Short(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::Float(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::Int(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::Double(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::Long(v) => out.extend_from_slice(&v.to_be_bytes()),
+    }
+}
+
+fn decode_fields(bytes: &mut &[u8]) -> Result<Vec<FieldInfo>, Error> {
+    let count = U2::from_be_bytes(take(bytes)?);
+    (0..count)
+        .map(|_| {
+            let name_id = StringId(read_u8(bytes)?);
+            let [tag, has_value] = take(bytes)?;
+            let field_type = BasicType::try_from(tag)?;
+            let value = if has_value == 0 {
+                None
+            } else {
+                Some(decode_value(field_type, bytes)?)
+            };
+            Ok(FieldInfo {
+                name_id,
+                field_type,
+                value,
+            })
+        })
+        .collect()
+}
+
+fn decode_value(field_type: BasicType, bytes: &mut &[u8]) -> Result<Value, Error> {
+    let value = match field_type {
+        BasicType::Object => Value::Object {
+            object_id: ObjectId(read_u8(bytes)?),
+        },
+        BasicType::Boolean => Value::Boolean(take::<1>(bytes)?[0] != 0),
+        BasicType::Char => Value::Char(u16::from_be_bytes(take(bytes)?)),
+        BasicType::Float => Value::Float(f32::from_be_bytes(take(bytes)?)),
+        BasicType::Double => Value::Double(f64::from_be_bytes(take(bytes)?)),
+        BasicType::Byte => Value::Byte(i8::from_be_bytes(take(bytes)?)),
+        BasicType::Short => Value::Short(i16::from_be_bytes(take(bytes)?)),
+        BasicType::Int => Value::Int(i32::from_be_bytes(take(bytes)?)),
+        BasicType::Long => Value::Long(i64::from_be_bytes(take(bytes)?)),
+    };
+    Ok(value)
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], Error> {
+    let (head, tail) = bytes
+        .split_first_chunk::<N>()
+        .ok_or(Error::from(ErrorKind::UnexpectedEof))?;
+    *bytes = tail;
+    Ok(*head)
+}
+
+fn read_u4(bytes: &mut &[u8]) -> Result<U4, Error> {
+    take(bytes).map(U4::from_be_bytes)
+}
+
+fn read_u8(bytes: &mut &[u8]) -> Result<U8, Error> {
+    take(bytes).map(U8::from_be_bytes)
+}
+
+/// Keeps shared values in a [`HashMap`].
+pub struct HashTable<V: Storable + ?Sized> {
+    values: HashMap<U8, Arc<V>>,
+}
+
+impl<V: Storable + ?Sized> Default for HashTable<V> {
+    fn default() -> Self {
+        HashTable {
+            values: HashMap::new(),
+        }
+    }
+}
+
+impl<V: Storable + Send + Sync + ?Sized> Table<V> for HashTable<V> {
+    fn insert(&mut self, id: U8, value: &Arc<V>) -> Result<(), Error> {
+        self.values.insert(id, value.clone());
+        Ok(())
+    }
+
+    fn get(&self, id: U8) -> Result<Option<Cow<'_, V>>, Error> {
+        Ok(self.values.get(&id).map(|v| Cow::Borrowed(v.as_ref())))
+    }
+
+    fn ids(&self) -> Vec<U8> {
+        self.values.keys().copied().collect()
+    }
+}
+
+/// Encodes the values back to back into one buffer.
+#[derive(Default)]
+pub struct ArenaTable {
+    bytes: Vec<u8>,
+    index: HashMap<U8, (U8, U4)>,
+}
+
+impl<V: Storable + ?Sized> Table<V> for ArenaTable {
+    fn insert(&mut self, id: U8, value: &Arc<V>) -> Result<(), Error> {
+        let offset = self.bytes.len();
+        value.encode(&mut self.bytes);
+        let len = (self.bytes.len() - offset) as U4;
+        self.index.insert(id, (offset as U8, len));
+        Ok(())
+    }
+
+    fn get(&self, id: U8) -> Result<Option<Cow<'_, V>>, Error> {
+        self.index
+            .get(&id)
+            .map(|&(offset, len)| V::decode(&self.bytes[offset as usize..][..len as usize]))
+            .transpose()
+    }
+
+    fn ids(&self) -> Vec<U8> {
+        self.index.keys().copied().collect()
+    }
+}
+
+/// Encodes the values back to back into one buffer and keeps the index in sorted runs.
+///
+/// Runs are merged so that their lengths decrease, so there are at most `log2(n)` runs
+/// to search for a lookup.
+#[derive(Default)]
+pub struct SortedVecTable {
+    bytes: Vec<u8>,
+    runs: Vec<Vec<(U8, U8, U4)>>,
+}
+
+impl SortedVecTable {
+    fn find(&self, id: U8) -> Option<(U8, U4)> {
+        // search newer runs first, so replaced values are not found
+        self.runs.iter().rev().find_map(|run| {
+            run.binary_search_by_key(&id, |&(id, _, _)| id)
+                .ok()
+                .map(|i| (run[i].1, run[i].2))
+        })
+    }
+}
+
+impl<V: Storable + ?Sized> Table<V> for SortedVecTable {
+    fn insert(&mut self, id: U8, value: &Arc<V>) -> Result<(), Error> {
+        let offset = self.bytes.len();
+        value.encode(&mut self.bytes);
+        let len = (self.bytes.len() - offset) as U4;
+        self.runs.push(vec![(id, offset as U8, len)]);
+        while self.runs.len() >= 2 {
+            let newer = &self.runs[self.runs.len() - 1];
+            let older = &self.runs[self.runs.len() - 2];
+            if older.len() > newer.len() {
+                break;
+            }
+            let newer = self.runs.pop().unwrap();
+            let older = self.runs.pop().unwrap();
+            self.runs.push(merge_runs(older, newer));
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: U8) -> Result<Option<Cow<'_, V>>, Error> {
+        self.find(id)
+            .map(|(offset, len)| V::decode(&self.bytes[offset as usize..][..len as usize]))
+            .transpose()
+    }
+
+    fn ids(&self) -> Vec<U8> {
+        let mut ids: Vec<U8> = self.runs.iter().flatten().map(|&(id, _, _)| id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+}
+
+/// Merges two sorted runs, preferring entries of `newer` for equal ids.
+fn merge_runs(older: Vec<(U8, U8, U4)>, newer: Vec<(U8, U8, U4)>) -> Vec<(U8, U8, U4)> {
+    let mut merged = Vec::with_capacity(older.len() + newer.len());
+    let mut older = older.into_iter().peekable();
+    let mut newer = newer.into_iter().peekable();
+    loop {
+        match (older.peek(), newer.peek()) {
+            (Some(o), Some(n)) if o.0 < n.0 => merged.push(older.next().unwrap()),
+            (Some(o), Some(n)) if o.0 == n.0 => {
+                older.next();
+                merged.push(newer.next().unwrap());
+            }
+            (_, Some(_)) => merged.push(newer.next().unwrap()),
+            (Some(_), None) => merged.push(older.next().unwrap()),
+            (None, None) => return merged,
+        }
+    }
+}
+
+/// How many encoded bytes [`SpillTable`] buffers before writing them to its file.
+const SPILL_BUFFER_SIZE: usize = 1 << 20;
+
+/// Encodes the values into a temporary file, which is deleted when the table is dropped.
+pub struct SpillTable {
+    path: PathBuf,
+    file: File,
+    /// The number of bytes written to the file
+    written: U8,
+    /// Encoded values that are not written to the file yet
+    pending: Vec<u8>,
+    index: HashMap<U8, (U8, U4)>,
+}
+
+impl SpillTable {
+    pub fn new(directory: &Path) -> Result<SpillTable, Error> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let name = format!(
+            "hprof-rs-{}-{}.spill",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = directory.join(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(SpillTable {
+            path,
+            file,
+            written: 0,
+            pending: vec![],
+            index: HashMap::new(),
+        })
+    }
+
+    fn read(&self, offset: U8, len: U4) -> Result<Cow<'_, [u8]>, Error> {
+        if offset >= self.written {
+            let start = (offset - self.written) as usize;
+            return Ok(Cow::Borrowed(&self.pending[start..][..len as usize]));
+        }
+        let mut bytes = vec![0; len as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut bytes)?;
+        Ok(Cow::Owned(bytes))
+    }
+}
+
+impl<V: Storable + ?Sized> Table<V> for SpillTable {
+    fn insert(&mut self, id: U8, value: &Arc<V>) -> Result<(), Error> {
+        let start = self.pending.len();
+        value.encode(&mut self.pending);
+        let len = (self.pending.len() - start) as U4;
+        self.index.insert(id, (self.written + start as U8, len));
+        if self.pending.len() >= SPILL_BUFFER_SIZE {
+            self.file.seek(SeekFrom::Start(self.written))?;
+            self.file.write_all(&self.pending)?;
+            self.written += self.pending.len() as U8;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: U8) -> Result<Option<Cow<'_, V>>, Error> {
+        let Some(&(offset, len)) = self.index.get(&id) else {
+            return Ok(None);
+        };
+        match self.read(offset, len)? {
+            Cow::Borrowed(bytes) => V::decode(bytes).map(Some),
+            Cow::Owned(bytes) => V::decode(&bytes).map(|v| Some(Cow::Owned(v.into_owned()))),
+        }
+    }
+
+    fn ids(&self) -> Vec<U8> {
+        self.index.keys().copied().collect()
+    }
+}
+
+impl Drop for SpillTable {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
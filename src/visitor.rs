@@ -0,0 +1,149 @@
+use crate::hprof_model::SubRecordKind;
+use crate::hprof_model::{BasicType, ClassId, ClassSerial, ObjectId, StringId, TraceSerial, U4};
+use crate::hprof_model::{ClassInfo, HeapDumpTag, PrimitiveArray, RecordBase, RecordTag, Value};
+
+/// Tells the parser how to continue after a [`HprofVisitor`] callback.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Visit {
+    /// Continue with the next record or sub-record.
+    #[default]
+    Continue,
+    /// Skip the current sub-record without decoding its body. Only has an effect when returned
+    /// from [`HprofVisitor::visit_sub_record_header`], and is the same as [`Visit::Continue`]
+    /// otherwise.
+    Skip,
+    /// Skip the remaining sub-records of the current heap dump segment without parsing them.
+    /// Outside of a segment, this is the same as [`Visit::Continue`].
+    SkipSegment,
+    /// Stop parsing and return from [`crate::reader::HprofReader::accept`].
+    Stop,
+}
+
+/// Receives records and sub-records as they are parsed by
+/// [`crate::reader::HprofReader::accept`].
+///
+/// All methods do nothing by default, so implementations only need to override
+/// the callbacks they are interested in. The data passed to the callbacks is only
+/// borrowed for the duration of the call.
+#[allow(unused_variables)]
+pub trait HprofVisitor {
+    fn visit_utf8(&mut self, id: StringId, utf8: &str) -> Visit {
+        Visit::Continue
+    }
+
+    fn visit_load_class(
+        &mut self,
+        class_serial_number: ClassSerial,
+        class_object_id: ClassId,
+        stack_trace_serial_number: TraceSerial,
+        class_name_id: StringId,
+    ) -> Visit {
+        Visit::Continue
+    }
+
+    /// Called for top-level records that have no dedicated callback.
+    fn visit_record(&mut self, record: &RecordTag) -> Visit {
+        Visit::Continue
+    }
+
+    /// Called before the sub-records of a heap dump segment are read.
+    /// Return [`Visit::SkipSegment`] to skip the whole segment.
+    fn visit_heap_dump_segment(&mut self, base: &RecordBase) -> Visit {
+        Visit::Continue
+    }
+
+    /// Called with the kind and the first identifier of each sub-record of a segment before
+    /// its body is read. The identifier is the id of the object for object dumps and roots,
+    /// and the id of the class for class dumps. Return [`Visit::Skip`] to skip the sub-record.
+    ///
+    /// Skipped class dumps are still read, because they are needed to decode instances.
+    fn visit_sub_record_header(&mut self, kind: SubRecordKind, id: ObjectId) -> Visit {
+        Visit::Continue
+    }
+
+    fn visit_class_dump(&mut self, class: &ClassInfo) -> Visit {
+        Visit::Continue
+    }
+
+    fn visit_instance(
+        &mut self,
+        object_id: ObjectId,
+        stack_trace_serial_number: TraceSerial,
+        class_object_id: ClassId,
+        instance_field_values: &[Value],
+    ) -> Visit {
+        Visit::Continue
+    }
+
+    fn visit_object_array(
+        &mut self,
+        array_object_id: ObjectId,
+        stack_trace_serial_number: TraceSerial,
+        array_class_id: ClassId,
+        elements: &[ObjectId],
+    ) -> Visit {
+        Visit::Continue
+    }
+
+    /// `elements` is `None` if primitive array bodies are skipped by the reader options.
+    fn visit_primitive_array(
+        &mut self,
+        array_object_id: ObjectId,
+        stack_trace_serial_number: TraceSerial,
+        element_type: BasicType,
+        element_count: U4,
+        elements: Option<&PrimitiveArray>,
+    ) -> Visit {
+        Visit::Continue
+    }
+
+    /// Called for all `HPROF_GC_ROOT_*` sub-records.
+    fn visit_gc_root(&mut self, root: &HeapDumpTag) -> Visit {
+        Visit::Continue
+    }
+}
+
+pub(crate) fn visit_sub_record<V: HprofVisitor>(
+    visitor: &mut V,
+    sub_record: &HeapDumpTag,
+) -> Visit {
+    match sub_record {
+        HeapDumpTag::HprofGcClassDump(class) => visitor.visit_class_dump(class),
+        HeapDumpTag::HprofGcInstanceDump {
+            object_id,
+            stack_trace_serial_number,
+            class_object_id,
+            instance_field_values,
+        } => visitor.visit_instance(
+            *object_id,
+            *stack_trace_serial_number,
+            *class_object_id,
+            instance_field_values,
+        ),
+        HeapDumpTag::HprofGcObjArrayDump {
+            array_object_id,
+            stack_trace_serial_number,
+            array_class_id,
+            elements,
+        } => visitor.visit_object_array(
+            *array_object_id,
+            *stack_trace_serial_number,
+            *array_class_id,
+            elements,
+        ),
+        HeapDumpTag::HprofGcPrimArrayDump {
+            array_object_id,
+            stack_trace_serial_number,
+            element_type,
+            element_count,
+            elements,
+        } => visitor.visit_primitive_array(
+            *array_object_id,
+            *stack_trace_serial_number,
+            *element_type,
+            *element_count,
+            elements.as_ref(),
+        ),
+        root => visitor.visit_gc_root(root),
+    }
+}